@@ -0,0 +1,9 @@
+CREATE TABLE newsletter_issue_deliveries (
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id),
+	subscriber_email TEXT NOT NULL,
+	status TEXT NOT NULL,
+	error TEXT NULL,
+	recorded_at timestamptz NOT NULL,
+	PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,14 @@
+BEGIN;
+	-- published_at has always been filled with now()
+	ALTER TABLE newsletter_issues
+		ALTER COLUMN published_at TYPE timestamptz USING published_at::timestamptz;
+	-- Scheduled issues are not published yet
+	ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+	ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+	-- Backfill status for historical entries
+	ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+	UPDATE newsletter_issues
+		SET status = 'published'
+		WHERE status IS NULL;
+	ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+COMMIT;
@@ -0,0 +1,7 @@
+-- One token bucket per sending quota (per second, per hour, per day),
+-- shared by every delivery worker.
+CREATE TABLE email_rate_limits (
+	quota_name TEXT NOT NULL PRIMARY KEY,
+	tokens DOUBLE PRECISION NOT NULL,
+	refilled_at timestamptz NOT NULL
+);
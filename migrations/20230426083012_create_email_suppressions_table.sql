@@ -0,0 +1,8 @@
+-- Addresses we must never email again: hard bounces and spam complaints
+-- reported by the email provider.
+CREATE TABLE email_suppressions (
+	email TEXT NOT NULL PRIMARY KEY,
+	reason TEXT NOT NULL,
+	details TEXT NULL,
+	suppressed_at timestamptz NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Opens and clicks of newsletter issues, reported by the tracking
+-- pixel and the rewritten links.
+CREATE TABLE newsletter_issue_events (
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id),
+	subscriber_id uuid NOT NULL
+		REFERENCES subscriptions (id) ON DELETE CASCADE,
+	kind TEXT NOT NULL,
+	url TEXT NULL,
+	occurred_at timestamptz NOT NULL
+);
+CREATE INDEX newsletter_issue_events_issue_idx
+	ON newsletter_issue_events (newsletter_issue_id, kind);
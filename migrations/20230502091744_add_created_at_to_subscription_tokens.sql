@@ -0,0 +1,2 @@
+-- Tokens issued before this migration get the migration date.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
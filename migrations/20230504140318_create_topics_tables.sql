@@ -0,0 +1,12 @@
+-- Topics subscribers can pick on their preferences page.
+CREATE TABLE topics (
+	topic_id uuid PRIMARY KEY,
+	name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE subscriber_topics (
+	subscriber_id uuid NOT NULL
+		REFERENCES subscriptions (id) ON DELETE CASCADE,
+	topic_id uuid NOT NULL
+		REFERENCES topics (topic_id) ON DELETE CASCADE,
+	PRIMARY KEY (subscriber_id, topic_id)
+);
@@ -0,0 +1,3 @@
+-- Set on the tokens confirming an email change: following the link
+-- moves the subscriber to the new address instead of confirming them.
+ALTER TABLE subscription_tokens ADD COLUMN new_email TEXT NULL;
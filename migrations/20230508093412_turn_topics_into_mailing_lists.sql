@@ -0,0 +1,50 @@
+-- Topics become mailing lists: issues are sent to one or more lists,
+-- and every subscriber has a status on each of the lists they joined.
+ALTER TABLE topics RENAME TO lists;
+ALTER TABLE lists RENAME COLUMN topic_id TO list_id;
+ALTER TABLE lists RENAME CONSTRAINT topics_pkey TO lists_pkey;
+ALTER TABLE lists RENAME CONSTRAINT topics_name_key TO lists_name_key;
+-- Public identifier of the list, e.g. in subscription forms
+ALTER TABLE lists ADD COLUMN slug TEXT;
+UPDATE lists SET slug = list_id::text;
+ALTER TABLE lists ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE lists ADD CONSTRAINT lists_slug_key UNIQUE (slug);
+
+ALTER TABLE subscriber_topics RENAME TO list_subscriptions;
+ALTER TABLE list_subscriptions RENAME COLUMN topic_id TO list_id;
+ALTER TABLE list_subscriptions
+	RENAME CONSTRAINT subscriber_topics_pkey TO list_subscriptions_pkey;
+ALTER TABLE list_subscriptions
+	RENAME CONSTRAINT subscriber_topics_subscriber_id_fkey TO list_subscriptions_subscriber_id_fkey;
+ALTER TABLE list_subscriptions
+	RENAME CONSTRAINT subscriber_topics_topic_id_fkey TO list_subscriptions_list_id_fkey;
+-- Topics were picked by the subscribers themselves
+ALTER TABLE list_subscriptions ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed';
+ALTER TABLE list_subscriptions ALTER COLUMN status DROP DEFAULT;
+
+CREATE TABLE newsletter_issue_lists (
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id),
+	list_id uuid NOT NULL
+		REFERENCES lists (list_id),
+	PRIMARY KEY (newsletter_issue_id, list_id)
+);
+
+-- Everything sent so far went to a single list, which may already exist
+-- as a topic of the same name
+INSERT INTO lists (list_id, name, slug)
+VALUES (gen_random_uuid(), 'Newsletter', 'newsletter')
+ON CONFLICT (name) DO NOTHING;
+UPDATE lists SET slug = 'newsletter' WHERE name = 'Newsletter';
+INSERT INTO list_subscriptions (subscriber_id, list_id, status)
+SELECT
+	s.id,
+	l.list_id,
+	CASE WHEN s.status = 'pending_confirmation' THEN 'pending_confirmation' ELSE 'confirmed' END
+FROM subscriptions s, lists l
+WHERE l.slug = 'newsletter'
+ON CONFLICT (subscriber_id, list_id) DO NOTHING;
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT i.newsletter_issue_id, l.list_id
+FROM newsletter_issues i, lists l
+WHERE l.slug = 'newsletter';
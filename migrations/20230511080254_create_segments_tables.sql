@@ -0,0 +1,20 @@
+-- Free-form data about subscribers, used to build segments
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
+
+CREATE TABLE segments(
+    segment_id uuid NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    filter TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- Issues without a segment go to every confirmed subscriber of their lists
+ALTER TABLE newsletter_issues ADD COLUMN segment_id uuid NULL
+    REFERENCES segments (segment_id);
@@ -0,0 +1,3 @@
+-- The source of issues written in Markdown, kept so that they can be
+-- edited later. Their HTML and plain text contents are rendered from it.
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
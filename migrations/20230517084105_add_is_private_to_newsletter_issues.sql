@@ -0,0 +1,2 @@
+-- Private issues are left out of the public archive
+ALTER TABLE newsletter_issues ADD COLUMN is_private BOOLEAN NOT NULL DEFAULT false;
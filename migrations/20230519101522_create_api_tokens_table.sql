@@ -0,0 +1,10 @@
+-- Tokens are only shown once, when they are created: we keep a hash
+CREATE TABLE api_tokens(
+    token_id uuid NOT NULL,
+    PRIMARY KEY(token_id),
+    user_id uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz NULL
+);
@@ -0,0 +1,3 @@
+-- Expired tokens are kept, as the token history of the subscriber, until
+-- the subscriber is deleted. NULL while the token can still be used.
+ALTER TABLE subscription_tokens ADD COLUMN expired_at timestamptz NULL;
@@ -0,0 +1,110 @@
+use super::UserId;
+use crate::utils::e500;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderMap, AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::web;
+use actix_web::{HttpMessage, HttpResponse};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+/// Tokens start with a prefix, so that they are easy to recognise
+/// (e.g. by secret scanners) when they leak.
+const API_TOKEN_PREFIX: &str = "z2p_";
+
+/// Generate a random API token. Only its hash is stored.
+pub fn generate_api_token() -> String {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    format!("{API_TOKEN_PREFIX}{random}")
+}
+
+/// Tokens are long and random: unlike passwords, a fast hash is enough.
+pub fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Authenticate API requests with an `Authorization: Bearer <token>` header.
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = match bearer_token(req.headers()) {
+        Ok(token) => token,
+        Err(e) => return Err(unauthorized(e)),
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("the database pool is not available")
+        .map_err(e500)?;
+    let user_id = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1
+        RETURNING user_id
+        "#,
+        hash_api_token(token.as_str()),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("failed to look up the API token")
+    .map_err(e500)?;
+    match user_id {
+        Some(row) => {
+            req.extensions_mut().insert(UserId(row.user_id));
+            next.call(req).await
+        }
+        None => Err(unauthorized(anyhow::anyhow!("the API token is not valid"))),
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Result<String, anyhow::Error> {
+    let header_value = headers
+        .get(AUTHORIZATION)
+        .context("the 'Authorization' header was missing")?
+        .to_str()
+        .context("the 'Authorization' header was not a valid UTF8 string")?;
+    let token = header_value
+        .strip_prefix("Bearer ")
+        .context("the 'Authorization' scheme was not 'Bearer'")?;
+    Ok(token.trim().to_owned())
+}
+
+fn unauthorized(e: anyhow::Error) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, "Bearer"))
+        .json(serde_json::json!({ "error": e.to_string() }));
+    InternalError::from_response(e, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_api_token, hash_api_token};
+
+    #[test]
+    fn tokens_are_random_and_prefixed() {
+        let token = generate_api_token();
+        assert!(token.starts_with("z2p_"));
+        assert_eq!(token.len(), 44);
+        assert_ne!(token, generate_api_token());
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        let token = generate_api_token();
+        assert_eq!(hash_api_token(&token), hash_api_token(&token));
+        assert_ne!(
+            hash_api_token(&token),
+            hash_api_token(&generate_api_token())
+        );
+    }
+}
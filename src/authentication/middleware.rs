@@ -10,7 +10,7 @@ use std::ops::Deref;
 use uuid::Uuid;
 
 #[derive(Copy, Clone, Debug)]
-pub struct UserId(Uuid);
+pub struct UserId(pub(super) Uuid);
 
 impl std::fmt::Display for UserId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
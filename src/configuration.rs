@@ -1,17 +1,24 @@
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{EmailClient, FileSpoolTransport, PostmarkTransport, SmtpTransport};
+use crate::rate_limiter::RateLimiter;
 use config::Config;
 use secrecy::ExposeSecret;
 use secrecy::Secret;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
+use std::num::NonZeroU32;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub worker: WorkerSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub idempotency: IdempotencySettings,
     // The URI is marked as secret because it may contain a password
     pub redis_uri: Secret<String>,
 }
@@ -25,6 +32,92 @@ pub struct ApplicationSettings {
     pub hmac_secret: Secret<String>,
 }
 
+#[derive(serde::Deserialize, Clone)]
+pub struct WorkerSettings {
+    /// Number of delivery workers consuming the queue concurrently
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub n_workers: usize,
+    /// How long an idle worker waits before polling the queue again
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_milliseconds: u64,
+    /// How long a worker waits after an unexpected error
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub error_backoff_milliseconds: u64,
+    /// Add an open tracking pixel to issues and route their links
+    /// through the click tracking endpoint
+    pub track_opens_and_clicks: bool,
+}
+
+impl WorkerSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_milliseconds)
+    }
+
+    pub fn error_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.error_backoff_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    /// How long a confirmation link stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_token_ttl_hours: u32,
+    /// Pending subscribers without a valid confirmation link are deleted
+    /// once they signed up this long ago
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub abandoned_after_days: u32,
+    /// Expired confirmation tokens are kept this long as the token history
+    /// of the subscriber, then deleted
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub expired_token_retention_days: u32,
+    /// How often expired tokens and abandoned subscribers are cleaned up
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_minutes: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.confirmation_token_ttl_hours.into())
+    }
+
+    pub fn abandoned_after(&self) -> chrono::Duration {
+        chrono::Duration::days(self.abandoned_after_days.into())
+    }
+
+    pub fn expired_token_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.expired_token_retention_days.into())
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_minutes * 60)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IdempotencySettings {
+    /// How long the response saved for an idempotency key is replayed:
+    /// past that, the key is treated as new
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retention_hours: u32,
+    /// How often expired idempotency keys are deleted
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_minutes: u64,
+    /// Maximum number of keys deleted by a single statement
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_batch_size: NonZeroU32,
+}
+
+impl IdempotencySettings {
+    pub fn retention(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.retention_hours.into())
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_minutes * 60)
+    }
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
@@ -61,22 +154,87 @@ impl DatabaseSettings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
+    #[serde(default)]
+    pub transport: EmailTransportKind,
     pub sender_email: String,
-    pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    // Postmark
+    pub base_url: String,
+    pub authorization_token: Secret<String>,
+    pub smtp: SmtpSettings,
+    pub spool_directory: String,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    /// Shared secret authenticating the provider's bounce webhooks
+    pub webhook_secret: Secret<String>,
+}
+
+/// How emails leave the application.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransportKind {
+    /// Postmark's HTTP API
+    #[default]
+    Postmark,
+    /// Any SMTP relay
+    Smtp,
+    /// `.eml` files written to `spool_directory`, for local development
+    FileSpool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub require_tls: bool,
+}
+
+/// Sending quotas of the email provider. A missing value means no limit.
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct RateLimitSettings {
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub per_second: Option<NonZeroU32>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub per_hour: Option<NonZeroU32>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub per_day: Option<NonZeroU32>,
+}
+
+impl RateLimitSettings {
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.per_second, self.per_hour, self.per_day)
+    }
 }
 
 impl EmailClientSettings {
     pub fn client(self) -> EmailClient {
         let sender_email = self.sender().expect("invlid sender email address");
         let timeout = self.timeout();
-        EmailClient::new(
-            self.base_url,
-            sender_email,
-            self.authorization_token,
-            timeout,
-        )
+        match self.transport {
+            EmailTransportKind::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkTransport::new(self.base_url, self.authorization_token, timeout),
+            ),
+            EmailTransportKind::Smtp => {
+                let smtp = self.smtp;
+                let credentials = smtp.username.zip(smtp.password);
+                let transport = SmtpTransport::new(
+                    &smtp.host,
+                    smtp.port,
+                    credentials,
+                    smtp.require_tls,
+                    timeout,
+                )
+                .expect("invalid SMTP settings");
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportKind::FileSpool => {
+                EmailClient::new(sender_email, FileSpoolTransport::new(self.spool_directory))
+            }
+        }
     }
 
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
@@ -1,7 +1,15 @@
 mod new_subscriber;
+mod preferences_token;
+mod segment_filter;
 mod subscriber_email;
 mod subscriber_name;
+mod tracking_token;
+mod unsubscribe_token;
 
 pub use new_subscriber::NewSubscriber;
+pub use preferences_token::PreferencesToken;
+pub use segment_filter::{is_valid_attribute_key, AttributeValue, Operator, SegmentFilter};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use tracking_token::{TrackedAction, TrackedEvent, TrackingToken};
+pub use unsubscribe_token::UnsubscribeToken;
@@ -0,0 +1,122 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A token identifying a subscriber in preferences links.
+///
+/// Unlike `UnsubscribeToken`, it lets the bearer change the email address
+/// of the subscriber: it expires, and its HMAC tag is computed for this
+/// purpose only, so that neither token can stand in for the other.
+#[derive(Debug)]
+pub struct PreferencesToken(String);
+
+impl PreferencesToken {
+    pub fn new(
+        subscriber_id: Uuid,
+        expires_at: DateTime<Utc>,
+        hmac_secret: &Secret<String>,
+    ) -> Self {
+        let expires_at = expires_at.timestamp();
+        let tag = mac(subscriber_id, expires_at, hmac_secret)
+            .finalize()
+            .into_bytes();
+        Self(format!(
+            "{}.{}.{}",
+            subscriber_id.simple(),
+            expires_at,
+            URL_SAFE_NO_PAD.encode(tag)
+        ))
+    }
+
+    /// Verify the token and return the id of the subscriber it was issued for.
+    pub fn verify(token: &str, hmac_secret: &Secret<String>) -> Result<Uuid, anyhow::Error> {
+        let parts: Vec<&str> = token.split('.').collect();
+        let [subscriber_id, expires_at, tag] = parts.as_slice() else {
+            anyhow::bail!("the preferences token is malformed");
+        };
+        let subscriber_id: Uuid = subscriber_id.parse()?;
+        let expires_at: i64 = expires_at.parse()?;
+        let tag = URL_SAFE_NO_PAD.decode(tag)?;
+        mac(subscriber_id, expires_at, hmac_secret).verify_slice(&tag)?;
+        if Utc.timestamp_opt(expires_at, 0).single() <= Some(Utc::now()) {
+            anyhow::bail!("the preferences token has expired");
+        }
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, expires_at: i64, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes()).unwrap();
+    mac.update(b"preferences:");
+    mac.update(subscriber_id.as_bytes());
+    mac.update(&expires_at.to_be_bytes());
+    mac
+}
+
+impl AsRef<str> for PreferencesToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreferencesToken;
+    use crate::domain::UnsubscribeToken;
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    fn tomorrow() -> chrono::DateTime<Utc> {
+        Utc::now() + Duration::days(1)
+    }
+
+    #[test]
+    fn a_generated_token_is_verified_successfully() {
+        let subscriber_id = Uuid::new_v4();
+        let token = PreferencesToken::new(subscriber_id, tomorrow(), &secret());
+        assert_ok_eq!(
+            PreferencesToken::verify(token.as_ref(), &secret()),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let yesterday = Utc::now() - Duration::days(1);
+        let token = PreferencesToken::new(Uuid::new_v4(), yesterday, &secret());
+        assert_err!(PreferencesToken::verify(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn a_token_with_a_pushed_back_expiry_is_rejected() {
+        let yesterday = Utc::now() - Duration::days(1);
+        let token = PreferencesToken::new(Uuid::new_v4(), yesterday, &secret());
+        let (subscriber_id, rest) = token.as_ref().split_once('.').unwrap();
+        let (_, tag) = rest.split_once('.').unwrap();
+        let forged = format!("{subscriber_id}.{}.{tag}", tomorrow().timestamp());
+        assert_err!(PreferencesToken::verify(&forged, &secret()));
+    }
+
+    #[test]
+    fn an_unsubscribe_token_is_rejected() {
+        let token = UnsubscribeToken::new(Uuid::new_v4(), &secret());
+        assert_err!(PreferencesToken::verify(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let another_secret = Secret::new("another-key".into());
+        let token = PreferencesToken::new(Uuid::new_v4(), tomorrow(), &another_secret);
+        assert_err!(PreferencesToken::verify(token.as_ref(), &secret()));
+    }
+}
@@ -0,0 +1,549 @@
+use sqlx::{Postgres, QueryBuilder};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// How deeply `not`s and parentheses can be nested, so that parsing a
+/// filter cannot overflow the stack.
+const MAX_NESTING_DEPTH: usize = 32;
+
+/// The filter of a segment: which confirmed subscribers an issue is sent to.
+///
+/// Conditions are combined with `and`, `or`, `not` and parentheses:
+///
+/// - `tag = "beta"` (or `!=`): the subscriber has (or has not) the tag;
+/// - `subscribed_days_ago <= 30`: how long ago the subscriber signed up,
+///   in days, compared with any of `=`, `!=`, `<`, `<=`, `>` and `>=`;
+/// - `attributes.plan = "pro"`: compares an attribute of the subscriber
+///   with a string, a number or a boolean (`true`, `false`). Strings and
+///   numbers can use any comparison operator, booleans `=` and `!=`.
+///   A condition on a missing attribute never matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentFilter {
+    Tag(String),
+    SubscribedDaysAgo(Operator, i32),
+    Attribute {
+        key: String,
+        operator: Operator,
+        value: AttributeValue,
+    },
+    Not(Box<SegmentFilter>),
+    /// A whole chain of `and`s, kept flat so that a long chain cannot
+    /// overflow the stack: always holds at least two filters.
+    And(Vec<SegmentFilter>),
+    /// See [`SegmentFilter::And`].
+    Or(Vec<SegmentFilter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl SegmentFilter {
+    pub fn parse(s: &str) -> Result<SegmentFilter, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?.into_iter().peekable(),
+            depth: 0,
+        };
+        let filter = parser.or()?;
+        match parser.tokens.next() {
+            None => Ok(filter),
+            Some(token) => Err(format!("unexpected {} in the filter", token.describe())),
+        }
+    }
+
+    /// Push the filter as an SQL condition, with the subscriber
+    /// aliased as `s`. Every value is sent as a bind parameter.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            SegmentFilter::Tag(tag) => {
+                query.push(
+                    "EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = ",
+                );
+                query.push_bind(tag.clone());
+                query.push(")");
+            }
+            SegmentFilter::SubscribedDaysAgo(operator, days) => {
+                query.push("(now() - s.subscribed_at) ");
+                query.push(operator.as_sql());
+                query.push(" make_interval(days => ");
+                query.push_bind(*days);
+                query.push(")");
+            }
+            SegmentFilter::Attribute {
+                key,
+                operator,
+                value,
+            } => {
+                // A missing attribute, or one of another type, makes the
+                // comparison NULL: it is turned into `false` so that it
+                // doesn't leak through a `not`.
+                query.push("COALESCE(");
+                match value {
+                    AttributeValue::String(value) => {
+                        query.push("s.attributes ->> ");
+                        query.push_bind(key.clone());
+                        query.push(" ");
+                        query.push(operator.as_sql());
+                        query.push(" ");
+                        query.push_bind(value.clone());
+                    }
+                    AttributeValue::Number(value) => {
+                        query.push("CASE WHEN jsonb_typeof(s.attributes -> ");
+                        query.push_bind(key.clone());
+                        query.push(") = 'number' THEN (s.attributes ->> ");
+                        query.push_bind(key.clone());
+                        query.push(")::float8 END ");
+                        query.push(operator.as_sql());
+                        query.push(" ");
+                        query.push_bind(*value);
+                    }
+                    AttributeValue::Bool(value) => {
+                        query.push("s.attributes -> ");
+                        query.push_bind(key.clone());
+                        query.push(" ");
+                        query.push(operator.as_sql());
+                        query.push(" to_jsonb(");
+                        query.push_bind(*value);
+                        query.push(")");
+                    }
+                }
+                query.push(", false)");
+            }
+            SegmentFilter::Not(filter) => {
+                query.push("NOT (");
+                filter.push_sql(query);
+                query.push(")");
+            }
+            SegmentFilter::And(filters) | SegmentFilter::Or(filters) => {
+                let separator = if matches!(self, SegmentFilter::And(_)) {
+                    ") AND ("
+                } else {
+                    ") OR ("
+                };
+                query.push("(");
+                for (i, filter) in filters.iter().enumerate() {
+                    if i > 0 {
+                        query.push(separator);
+                    }
+                    filter.push_sql(query);
+                }
+                query.push(")");
+            }
+        }
+    }
+}
+
+impl Operator {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Operator::Eq => "=",
+            Operator::NotEq => "<>",
+            Operator::Lt => "<",
+            Operator::LtEq => "<=",
+            Operator::Gt => ">",
+            Operator::GtEq => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    String(String),
+    Number(f64),
+    Operator(Operator),
+    OpenParen,
+    CloseParen,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Identifier(identifier) => format!("`{identifier}`"),
+            Token::String(s) => format!("\"{s}\""),
+            Token::Number(n) => format!("`{n}`"),
+            Token::Operator(Operator::NotEq) => "`!=`".into(),
+            Token::Operator(operator) => format!("`{}`", operator.as_sql()),
+            Token::OpenParen => "`(`".into(),
+            Token::CloseParen => "`)`".into(),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::OpenParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::CloseParen);
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let followed_by_eq = chars.next_if(|&(_, c)| c == '=').is_some();
+                let operator = match (c, followed_by_eq) {
+                    ('=', false) => Operator::Eq,
+                    ('!', true) => Operator::NotEq,
+                    ('<', false) => Operator::Lt,
+                    ('<', true) => Operator::LtEq,
+                    ('>', false) => Operator::Gt,
+                    ('>', true) => Operator::GtEq,
+                    _ => return Err(format!("unexpected `{c}` at position {start}")),
+                };
+                tokens.push(Token::Operator(operator));
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::String(string_literal(&mut chars)?));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let literal = take_while(s, &mut chars, |c| {
+                    c.is_ascii_digit() || c == '.' || c == '-'
+                });
+                let number = literal
+                    .parse()
+                    .map_err(|_| format!("{literal} is not a valid number"))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let identifier = take_while(s, &mut chars, |c| {
+                    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+                });
+                tokens.push(Token::Identifier(identifier.to_owned()));
+            }
+            c => return Err(format!("unexpected `{c}` at position {start}")),
+        }
+    }
+    Ok(tokens)
+}
+
+fn take_while<'a>(
+    s: &'a str,
+    chars: &mut Peekable<CharIndices<'_>>,
+    predicate: impl Fn(char) -> bool,
+) -> &'a str {
+    let start = chars.peek().map_or(s.len(), |&(i, _)| i);
+    while chars.next_if(|&(_, c)| predicate(c)).is_some() {}
+    let end = chars.peek().map_or(s.len(), |&(i, _)| i);
+    &s[start..end]
+}
+
+/// The opening quote has been consumed already.
+fn string_literal(chars: &mut Peekable<CharIndices<'_>>) -> Result<String, String> {
+    let mut literal = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '"')) => return Ok(literal),
+            Some((_, '\\')) => match chars.next() {
+                Some((_, c)) => literal.push(c),
+                None => break,
+            },
+            Some((_, c)) => literal.push(c),
+            None => break,
+        }
+    }
+    Err("a string is missing its closing quote".into())
+}
+
+/// Recursive descent over:
+///
+/// ```text
+/// or        := and ("or" and)*
+/// and       := unary ("and" unary)*
+/// unary     := "not" unary | "(" or ")" | condition
+/// condition := identifier operator (string | number | "true" | "false")
+/// ```
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+    /// How many `not`s and parentheses enclose the current position
+    depth: usize,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<SegmentFilter, String> {
+        let mut filters = vec![self.and()?];
+        while self.next_if_keyword("or") {
+            filters.push(self.and()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.pop().unwrap()
+        } else {
+            SegmentFilter::Or(filters)
+        })
+    }
+
+    fn and(&mut self) -> Result<SegmentFilter, String> {
+        let mut filters = vec![self.unary()?];
+        while self.next_if_keyword("and") {
+            filters.push(self.unary()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.pop().unwrap()
+        } else {
+            SegmentFilter::And(filters)
+        })
+    }
+
+    fn unary(&mut self) -> Result<SegmentFilter, String> {
+        if self.next_if_keyword("not") {
+            let filter = self.nested(Self::unary)?;
+            return Ok(SegmentFilter::Not(Box::new(filter)));
+        }
+        if self.tokens.next_if_eq(&Token::OpenParen).is_some() {
+            let filter = self.nested(Self::or)?;
+            return match self.tokens.next() {
+                Some(Token::CloseParen) => Ok(filter),
+                Some(token) => Err(format!("expected `)`, found {}", token.describe())),
+                None => Err("a `(` is never closed".into()),
+            };
+        }
+        self.condition()
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<SegmentFilter, String>,
+    ) -> Result<SegmentFilter, String> {
+        if self.depth == MAX_NESTING_DEPTH {
+            return Err(format!(
+                "the filter is nested more than {MAX_NESTING_DEPTH} levels deep"
+            ));
+        }
+        self.depth += 1;
+        let filter = parse(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn condition(&mut self) -> Result<SegmentFilter, String> {
+        let field = match self.tokens.next() {
+            Some(Token::Identifier(field)) => field,
+            Some(token) => return Err(format!("expected a condition, found {}", token.describe())),
+            None => return Err("the filter ends where a condition is expected".into()),
+        };
+        let operator = match self.tokens.next() {
+            Some(Token::Operator(operator)) => operator,
+            Some(token) => {
+                return Err(format!(
+                    "expected an operator after `{field}`, found {}",
+                    token.describe()
+                ))
+            }
+            None => return Err(format!("expected an operator after `{field}`")),
+        };
+        let value = match self.tokens.next() {
+            Some(Token::String(s)) => AttributeValue::String(s),
+            Some(Token::Number(n)) => AttributeValue::Number(n),
+            Some(Token::Identifier(b)) if b == "true" || b == "false" => {
+                AttributeValue::Bool(b == "true")
+            }
+            Some(token) => {
+                return Err(format!(
+                    "expected a value after `{field}`, found {}",
+                    token.describe()
+                ))
+            }
+            None => return Err(format!("expected a value after `{field}`")),
+        };
+
+        if field == "tag" {
+            let AttributeValue::String(tag) = value else {
+                return Err("tags are compared with a string".into());
+            };
+            return match operator {
+                Operator::Eq => Ok(SegmentFilter::Tag(tag)),
+                Operator::NotEq => Ok(SegmentFilter::Not(Box::new(SegmentFilter::Tag(tag)))),
+                _ => Err("tags can only be compared with `=` and `!=`".into()),
+            };
+        }
+        if field == "subscribed_days_ago" {
+            return match value {
+                AttributeValue::Number(days) if days.fract() == 0.0 && days.abs() < 1e6 => {
+                    Ok(SegmentFilter::SubscribedDaysAgo(operator, days as i32))
+                }
+                _ => Err("`subscribed_days_ago` is compared with a whole number of days".into()),
+            };
+        }
+        let Some(key) = field.strip_prefix("attributes.") else {
+            return Err(format!(
+                "`{field}` is not a known field: use `tag`, `subscribed_days_ago` or `attributes.<name>`"
+            ));
+        };
+        if !is_valid_attribute_key(key) {
+            return Err(format!("`{key}` is not a valid attribute name"));
+        }
+        if matches!(value, AttributeValue::Bool(_))
+            && !matches!(operator, Operator::Eq | Operator::NotEq)
+        {
+            return Err("booleans can only be compared with `=` and `!=`".into());
+        }
+        Ok(SegmentFilter::Attribute {
+            key: key.to_owned(),
+            operator,
+            value,
+        })
+    }
+
+    fn next_if_keyword(&mut self, keyword: &str) -> bool {
+        self.tokens
+            .next_if(|t| matches!(t, Token::Identifier(i) if i == keyword))
+            .is_some()
+    }
+}
+
+/// Attribute names are made of ASCII letters, digits and underscores,
+/// so that they can be used in filters.
+pub fn is_valid_attribute_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= 50 && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributeValue, Operator, SegmentFilter, MAX_NESTING_DEPTH};
+    use claims::assert_err;
+    use sqlx::{Execute, Postgres, QueryBuilder};
+
+    fn tag(tag: &str) -> SegmentFilter {
+        SegmentFilter::Tag(tag.into())
+    }
+
+    #[test]
+    fn conditions_are_parsed() {
+        assert_eq!(SegmentFilter::parse(r#"tag = "beta""#), Ok(tag("beta")));
+        assert_eq!(
+            SegmentFilter::parse("subscribed_days_ago <= 30"),
+            Ok(SegmentFilter::SubscribedDaysAgo(Operator::LtEq, 30))
+        );
+        assert_eq!(
+            SegmentFilter::parse("attributes.age >= 18.5"),
+            Ok(SegmentFilter::Attribute {
+                key: "age".into(),
+                operator: Operator::GtEq,
+                value: AttributeValue::Number(18.5),
+            })
+        );
+        assert_eq!(
+            SegmentFilter::parse("attributes.paying != false"),
+            Ok(SegmentFilter::Attribute {
+                key: "paying".into(),
+                operator: Operator::NotEq,
+                value: AttributeValue::Bool(false),
+            })
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let filter = SegmentFilter::parse(r#"tag = "a" or tag = "b" and not tag = "c""#);
+        assert_eq!(
+            filter,
+            Ok(SegmentFilter::Or(vec![
+                tag("a"),
+                SegmentFilter::And(vec![tag("b"), SegmentFilter::Not(Box::new(tag("c")))]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_conditions() {
+        let filter = SegmentFilter::parse(r#"(tag = "a" or tag = "b") and tag != "c""#);
+        assert_eq!(
+            filter,
+            Ok(SegmentFilter::And(vec![
+                SegmentFilter::Or(vec![tag("a"), tag("b")]),
+                SegmentFilter::Not(Box::new(tag("c"))),
+            ]))
+        );
+    }
+
+    #[test]
+    fn strings_can_contain_escaped_quotes() {
+        assert_eq!(
+            SegmentFilter::parse(r#"tag = "say \"hi\"""#),
+            Ok(tag(r#"say "hi""#))
+        );
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for filter in [
+            "",
+            r#"tag = "beta" and"#,
+            r#"(tag = "beta""#,
+            r#"tag < "beta""#,
+            "tag = 1",
+            "subscribed_days_ago < 1.5",
+            r#"email = "a@example.com""#,
+            "attributes. = 1",
+            "attributes.paying > true",
+            r#"tag = "beta"#,
+            r#"tag = "beta" tag = "alpha""#,
+            "attributes.plan =",
+            "attributes.plan ~ 1",
+        ] {
+            assert_err!(SegmentFilter::parse(filter), "{filter} was accepted");
+        }
+    }
+
+    #[test]
+    fn deeply_nested_filters_are_rejected() {
+        let nested = |depth: usize, open: &str, close: &str| {
+            format!(r#"{}tag = "a"{}"#, open.repeat(depth), close.repeat(depth))
+        };
+        assert!(SegmentFilter::parse(&nested(MAX_NESTING_DEPTH, "(", ")")).is_ok());
+        assert!(SegmentFilter::parse(&nested(MAX_NESTING_DEPTH, "not ", "")).is_ok());
+        // Deep enough to overflow the stack without a limit
+        for filter in [
+            nested(100_000, "(", ")"),
+            nested(100_000, "not ", ""),
+            nested(MAX_NESTING_DEPTH + 1, "not (", ")"),
+        ] {
+            assert_err!(SegmentFilter::parse(&filter));
+        }
+    }
+
+    #[test]
+    fn chains_of_conditions_are_kept_flat() {
+        assert_eq!(
+            SegmentFilter::parse(r#"tag = "a" and tag = "b" and tag = "c""#),
+            Ok(SegmentFilter::And(vec![tag("a"), tag("b"), tag("c")]))
+        );
+        // Long enough to overflow the stack if every `and` nested the next one
+        let chain = vec![r#"tag = "a""#; 100_000].join(" and ");
+        let filter = SegmentFilter::parse(&chain).unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("SELECT 1 FROM subscriptions s WHERE ");
+        filter.push_sql(&mut query);
+        assert_eq!(query.build().sql().matches(") AND (").count(), 99_999);
+    }
+
+    #[test]
+    fn values_are_bound_and_not_inlined() {
+        let filter = SegmentFilter::parse(r#"tag = "'; DROP TABLE subscriptions; --""#).unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("SELECT 1 FROM subscriptions s WHERE ");
+        filter.push_sql(&mut query);
+        let sql = query.build().sql();
+        assert!(!sql.contains("DROP TABLE"));
+        assert!(sql.contains("t.tag = $1"));
+    }
+}
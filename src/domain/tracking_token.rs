@@ -0,0 +1,146 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// What a tracking token records when it comes back to us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackedAction {
+    /// The tracking pixel of the issue has been loaded
+    Open,
+    /// A link of the issue, pointing to this URL, has been followed
+    Click(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrackedEvent {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub action: TrackedAction,
+}
+
+/// A token embedded in the tracking pixel and in the rewritten links
+/// of an issue.
+///
+/// Like `UnsubscribeToken`, it carries its own data and an HMAC tag over it,
+/// so that it can't be forged, nor turned into an open redirect.
+#[derive(Debug)]
+pub struct TrackingToken(String);
+
+impl TrackingToken {
+    pub fn new(event: &TrackedEvent, hmac_secret: &Secret<String>) -> Self {
+        let tag = mac(event, hmac_secret).finalize().into_bytes();
+        let mut token = format!(
+            "{}.{}",
+            event.newsletter_issue_id.simple(),
+            event.subscriber_id.simple()
+        );
+        if let TrackedAction::Click(url) = &event.action {
+            token.push('.');
+            token.push_str(&URL_SAFE_NO_PAD.encode(url));
+        }
+        token.push('.');
+        token.push_str(&URL_SAFE_NO_PAD.encode(tag));
+        Self(token)
+    }
+
+    /// Verify the token and return the event it was issued for.
+    pub fn verify(
+        token: &str,
+        hmac_secret: &Secret<String>,
+    ) -> Result<TrackedEvent, anyhow::Error> {
+        let parts: Vec<&str> = token.split('.').collect();
+        let (newsletter_issue_id, subscriber_id, url, tag) = match parts.as_slice() {
+            [issue, subscriber, tag] => (issue, subscriber, None, tag),
+            [issue, subscriber, url, tag] => (issue, subscriber, Some(url), tag),
+            _ => anyhow::bail!("the tracking token is malformed"),
+        };
+        let action = match url {
+            Some(url) => TrackedAction::Click(String::from_utf8(URL_SAFE_NO_PAD.decode(url)?)?),
+            None => TrackedAction::Open,
+        };
+        let event = TrackedEvent {
+            newsletter_issue_id: newsletter_issue_id.parse()?,
+            subscriber_id: subscriber_id.parse()?,
+            action,
+        };
+        let tag = URL_SAFE_NO_PAD.decode(tag)?;
+        mac(&event, hmac_secret).verify_slice(&tag)?;
+        Ok(event)
+    }
+}
+
+fn mac(event: &TrackedEvent, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes()).unwrap();
+    mac.update(b"tracking:");
+    mac.update(event.newsletter_issue_id.as_bytes());
+    mac.update(event.subscriber_id.as_bytes());
+    match &event.action {
+        TrackedAction::Open => mac.update(b"open"),
+        TrackedAction::Click(url) => {
+            mac.update(b"click:");
+            mac.update(url.as_bytes());
+        }
+    }
+    mac
+}
+
+impl AsRef<str> for TrackingToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrackedAction, TrackedEvent, TrackingToken};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    fn click(url: &str) -> TrackedEvent {
+        TrackedEvent {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            action: TrackedAction::Click(url.into()),
+        }
+    }
+
+    #[test]
+    fn generated_tokens_are_verified_successfully() {
+        let open = TrackedEvent {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            action: TrackedAction::Open,
+        };
+        let click = click("https://example.com/?a=1&b=2");
+        for event in [open, click] {
+            let token = TrackingToken::new(&event, &secret());
+            assert_ok_eq!(TrackingToken::verify(token.as_ref(), &secret()), event);
+        }
+    }
+
+    #[test]
+    fn the_target_of_a_click_token_cannot_be_changed() {
+        let token = TrackingToken::new(&click("https://example.com"), &secret());
+        let mut parts: Vec<&str> = token.as_ref().split('.').collect();
+        // "https://evil.com", encoded
+        parts[2] = "aHR0cHM6Ly9ldmlsLmNvbQ";
+        assert_err!(TrackingToken::verify(&parts.join("."), &secret()));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = TrackingToken::new(
+            &click("https://example.com"),
+            &Secret::new("another-key".into()),
+        );
+        assert_err!(TrackingToken::verify(token.as_ref(), &secret()));
+    }
+}
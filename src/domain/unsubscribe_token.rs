@@ -0,0 +1,90 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A token identifying a subscriber in unsubscribe links.
+///
+/// It is made of the subscriber id and of an HMAC tag over it:
+/// it can't be forged without the application secret, and
+/// doesn't need to be stored in the database.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn new(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Self {
+        let tag = mac(subscriber_id, hmac_secret).finalize().into_bytes();
+        Self(format!(
+            "{}.{}",
+            subscriber_id.simple(),
+            URL_SAFE_NO_PAD.encode(tag)
+        ))
+    }
+
+    /// Verify the token and return the id of the subscriber it was issued for.
+    pub fn verify(token: &str, hmac_secret: &Secret<String>) -> Result<Uuid, anyhow::Error> {
+        let (subscriber_id, tag) = token
+            .split_once('.')
+            .ok_or_else(|| anyhow::anyhow!("the unsubscribe token is malformed"))?;
+        let subscriber_id: Uuid = subscriber_id.parse()?;
+        let tag = URL_SAFE_NO_PAD.decode(tag)?;
+        mac(subscriber_id, hmac_secret).verify_slice(&tag)?;
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes()).unwrap();
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_verified_successfully() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::new(subscriber_id, &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret()),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::new(Uuid::new_v4(), &Secret::new("another-key".into()));
+        assert_err!(UnsubscribeToken::verify(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = UnsubscribeToken::new(Uuid::new_v4(), &secret());
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4().simple(), tag);
+        assert_err!(UnsubscribeToken::verify(&forged, &secret()));
+    }
+
+    #[test]
+    fn a_malformed_token_is_rejected() {
+        assert_err!(UnsubscribeToken::verify("not-a-token", &secret()));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::email_client::{Email, EmailTransport};
+use anyhow::Context;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+
+/// Write every email as an `.eml` file in a directory instead of sending it.
+/// Meant for local development.
+pub struct FileSpoolTransport {
+    directory: PathBuf,
+    spool: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileSpoolTransport {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        Self {
+            spool: AsyncFileTransport::new(&directory),
+            directory,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileSpoolTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = email.to_mime_message()?;
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("failed to create the spool directory")?;
+        let id = self
+            .spool
+            .send(message)
+            .await
+            .context("failed to write the email to the spool directory")?;
+        tracing::info!(
+            path = %self.directory.join(format!("{id}.eml")).display(),
+            "email written to the spool directory"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, FileSpoolTransport};
+    use claims::assert_ok;
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_in_the_spool_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            FileSpoolTransport::new(&directory),
+        );
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient, "Hello", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("To: ursula@example.com"));
+        assert!(content.contains("Subject: Hello"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
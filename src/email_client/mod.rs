@@ -0,0 +1,149 @@
+mod file_spool;
+mod postmark;
+mod smtp;
+
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+
+pub use file_spool::FileSpoolTransport;
+pub use postmark::PostmarkTransport;
+pub use smtp::SmtpTransport;
+
+/// An email ready to be handed over to an `EmailTransport`.
+pub struct Email<'a> {
+    pub sender: &'a SubscriberEmail,
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: &'a [EmailHeader],
+}
+
+/// An email addressed to a single recipient, to be sent as part of a batch.
+pub struct OutgoingEmail {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub headers: Vec<EmailHeader>,
+}
+
+/// The outcome of a batch submission: the outer error means that the batch
+/// as a whole was rejected, otherwise there is one result per email, in order.
+pub type BatchOutcome = Result<Vec<Result<(), anyhow::Error>>, anyhow::Error>;
+
+/// The mechanism used to actually deliver emails
+/// (an HTTP API, an SMTP relay, the local filesystem, ...).
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error>;
+
+    /// Transports without a batch API send emails one at a time.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> BatchOutcome {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        Ok(outcomes)
+    }
+}
+
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Box<dyn EmailTransport>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            sender,
+            transport: Box::new(transport),
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Send an email carrying additional headers (e.g. `List-Unsubscribe`).
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), anyhow::Error> {
+        let email = Email {
+            sender: &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        };
+        self.transport.send(&email).await
+    }
+
+    /// Send several emails in as few requests as the transport allows.
+    pub async fn send_batch(&self, emails: &[OutgoingEmail]) -> BatchOutcome {
+        let emails: Vec<_> = emails
+            .iter()
+            .map(|e| Email {
+                sender: &self.sender,
+                recipient: &e.recipient,
+                subject: &e.subject,
+                html_content: &e.html_content,
+                text_content: &e.text_content,
+                headers: &e.headers,
+            })
+            .collect();
+        self.transport.send_batch(&emails).await
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl Email<'_> {
+    /// Build the MIME message (`multipart/alternative`, plain text and HTML)
+    /// used by the transports that do not talk to an HTTP API.
+    fn to_mime_message(&self) -> Result<Message, anyhow::Error> {
+        let from: Mailbox = self
+            .sender
+            .as_ref()
+            .parse()
+            .context("invalid sender address")?;
+        let to: Mailbox = self
+            .recipient
+            .as_ref()
+            .parse()
+            .context("invalid recipient address")?;
+        let mut builder = Message::builder().from(from).to(to).subject(self.subject);
+        for header in self.headers {
+            let name = HeaderName::new_from_ascii(header.name.clone())
+                .with_context(|| format!("invalid header name: {}", header.name))?;
+            builder = builder.raw_header(HeaderValue::new(name, header.value.clone()));
+        }
+        builder
+            .multipart(MultiPart::alternative_plain_html(
+                self.text_content.to_owned(),
+                self.html_content.to_owned(),
+            ))
+            .context("failed to build the email message")
+    }
+}
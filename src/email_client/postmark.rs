@@ -0,0 +1,450 @@
+use crate::email_client::{BatchOutcome, Email, EmailHeader, EmailTransport};
+use anyhow::Context;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+/// Postmark does not accept more than 500 messages per batch request.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Deliver emails through Postmark's HTTP API.
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest::from(email);
+
+        self.http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> BatchOutcome {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        let mut any_chunk_accepted = false;
+        let mut last_error = None;
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_outcomes) => {
+                    any_chunk_accepted = true;
+                    outcomes.extend(chunk_outcomes);
+                }
+                // The earlier chunks went through: only the recipients of
+                // this one must be retried.
+                Err(e) => {
+                    let message = format!("{e:#}");
+                    outcomes.extend(
+                        chunk
+                            .iter()
+                            .map(|_| Err(anyhow::anyhow!("the batch request failed: {message}"))),
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if !any_chunk_accepted => Err(e),
+            _ => Ok(outcomes),
+        }
+    }
+}
+
+impl PostmarkTransport {
+    /// Submit up to `MAX_BATCH_SIZE` emails in a single request.
+    async fn send_chunk(&self, chunk: &[Email<'_>]) -> BatchOutcome {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = chunk.iter().map(SendEmailRequest::from).collect();
+        let responses: Vec<SendEmailResponse> = self
+            .http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("failed to parse the response of the batch endpoint")?;
+        if responses.len() != chunk.len() {
+            anyhow::bail!(
+                "the batch endpoint returned {} results for {} emails",
+                responses.len(),
+                chunk.len()
+            );
+        }
+        // Results come back in the same order as the submitted messages
+        Ok(responses
+            .into_iter()
+            .map(|r| {
+                if r.error_code == 0 {
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!(
+                        "{} (error code {})",
+                        r.message,
+                        r.error_code
+                    ))
+                }
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+impl<'a> From<&'a Email<'a>> for SendEmailRequest<'a> {
+    fn from(email: &'a Email<'a>) -> Self {
+        Self {
+            from: email.sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: email.subject,
+            html_body: email.html_content,
+            text_body: email.text_content,
+            headers: email.headers,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_BATCH_SIZE;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, EmailHeader, OutgoingEmail, PostmarkTransport};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    /// Generate random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    /// Generate a random subscriber email
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// Get a test instance of `EmailClient`
+    fn email_client(base_url: String) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        EmailClient::new(email(), transport)
+    }
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            // Try to parse the result as JSON
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+            if let Ok(body) = result {
+                // Check that all the mandatory fields are populated
+                // without inspecting the field values
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_includes_them_in_the_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        }];
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{
+                    "Name": "List-Unsubscribe-Post",
+                    "Value": "List-Unsubscribe=One-Click",
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_it_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    /// Generate a random email, as sent in a batch
+    fn outgoing_email() -> OutgoingEmail {
+        OutgoingEmail {
+            recipient: email(),
+            subject: subject(),
+            html_content: content(),
+            text_content: content(),
+            headers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_each_email() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 300, "Message": "Invalid email request" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client
+            .send_batch(&[outgoing_email(), outgoing_email()])
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(outcomes.len(), 2);
+        assert_ok!(&outcomes[0]);
+        assert_err!(&outcomes[1]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&[outgoing_email()]).await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_keeps_the_outcomes_of_the_chunks_sent_before_a_failure() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let accepted_chunk: Vec<_> = (0..MAX_BATCH_SIZE)
+            .map(|_| serde_json::json!({ "ErrorCode": 0, "Message": "OK" }))
+            .collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(accepted_chunk))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let emails: Vec<_> = (0..MAX_BATCH_SIZE + 2).map(|_| outgoing_email()).collect();
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await.unwrap();
+
+        // Assert
+        assert_eq!(outcomes.len(), MAX_BATCH_SIZE + 2);
+        assert!(outcomes[..MAX_BATCH_SIZE].iter().all(|o| o.is_ok()));
+        assert!(outcomes[MAX_BATCH_SIZE..].iter().all(|o| o.is_err()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_if_some_results_are_missing() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!([{ "ErrorCode": 0, "Message": "OK" }])),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_batch(&[outgoing_email(), outgoing_email()])
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::email_client::{Email, EmailTransport};
+use anyhow::Context;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+/// Deliver emails through an SMTP relay.
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    /// When `require_tls` is set the connection is upgraded with STARTTLS
+    /// and emails are never sent in clear text.
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, Secret<String>)>,
+        require_tls: bool,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let mut builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("failed to set up the TLS parameters of the SMTP relay")?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = email.to_mime_message()?;
+        self.mailer
+            .send(message)
+            .await
+            .context("the SMTP relay rejected the email")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, EmailHeader, SmtpTransport};
+    use claims::{assert_err, assert_ok};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A minimal SMTP server accepting a single email.
+    /// Resolves to the raw content of the `DATA` command, without waiting
+    /// for the client to close its (pooled) connection.
+    async fn smtp_stand_in(listener: TcpListener, reject_recipient: bool) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        let mut data = String::new();
+        let mut in_data = false;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if in_data {
+                if line == "." {
+                    writer.write_all(b"250 OK\r\n").await.unwrap();
+                    break;
+                } else {
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                continue;
+            }
+            let command = line.to_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                b"250 localhost\r\n"
+            } else if command.starts_with("RCPT") && reject_recipient {
+                b"550 No such user\r\n"
+            } else if command.starts_with("DATA") {
+                in_data = true;
+                b"354 End data with <CR><LF>.<CR><LF>\r\n"
+            } else {
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        data
+    }
+
+    fn email_client(listener: &TcpListener) -> EmailClient {
+        let port = listener.local_addr().unwrap().port();
+        let transport = SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            None,
+            false,
+            std::time::Duration::from_secs(2),
+        )
+        .unwrap();
+        EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            transport,
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message_to_the_relay() {
+        // Arrange
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let email_client = email_client(&listener);
+        let server = tokio::spawn(smtp_stand_in(listener, false));
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        }];
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(
+                &recipient,
+                "Hello",
+                "<p>Hello as HTML</p>",
+                "Hello as plain text",
+                &headers,
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let data = server.await.unwrap();
+        assert!(data.contains("To: ursula@example.com"));
+        assert!(data.contains("Subject: Hello"));
+        assert!(data.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(data.contains("multipart/alternative"));
+        assert!(data.contains("Hello as plain text"));
+        assert!(data.contains("<p>Hello as HTML</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_rejects_the_recipient() {
+        // Arrange
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let email_client = email_client(&listener);
+        tokio::spawn(smtp_stand_in(listener, true));
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient, "Hello", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+}
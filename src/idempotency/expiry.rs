@@ -0,0 +1,72 @@
+use crate::configuration::{IdempotencySettings, Settings};
+use crate::startup::get_connection_pool;
+use chrono::Utc;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+pub async fn run_expiry_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    expiry_loop(&connection_pool, &configuration.idempotency, shutdown).await
+}
+
+async fn expiry_loop(
+    pool: &PgPool,
+    settings: &IdempotencySettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        // Errors are already logged by `delete_expired_keys`; try again on the next tick.
+        let _ = delete_expired_keys(pool, settings, &shutdown).await;
+        tokio::select! {
+            _ = shutdown.cancelled() => {},
+            _ = tokio::time::sleep(settings.cleanup_interval()) => {},
+        }
+    }
+    Ok(())
+}
+
+/// Delete the idempotency keys past their retention window, a batch at a
+/// time so that no statement holds locks on the whole table.
+/// Returns the number of deleted keys.
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_expired_keys(
+    pool: &PgPool,
+    settings: &IdempotencySettings,
+    shutdown: &CancellationToken,
+) -> Result<u64, anyhow::Error> {
+    let expired_before = Utc::now() - settings.retention();
+    let batch_size = settings.cleanup_batch_size.get();
+    let mut n_deleted_keys = 0;
+    while !shutdown.is_cancelled() {
+        // Keys locked by a request being processed are left for later
+        let n_deleted_rows = sqlx::query!(
+            r#"
+            DELETE FROM idempotency
+            WHERE (user_id, idempotency_key) IN (
+                SELECT user_id, idempotency_key
+                FROM idempotency
+                WHERE created_at <= $1
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            expired_before,
+            i64::from(batch_size),
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        n_deleted_keys += n_deleted_rows;
+        // A partial batch means that no expired key is left
+        if n_deleted_rows < u64::from(batch_size) {
+            break;
+        }
+    }
+    if n_deleted_keys > 0 {
+        tracing::info!(n_deleted_keys, "deleted expired idempotency keys");
+    }
+    Ok(n_deleted_keys)
+}
@@ -1,6 +1,8 @@
+mod expiry;
 mod key;
 mod persistence;
 
+pub use expiry::{delete_expired_keys, run_expiry_until_stopped};
 pub use key::IdempotencyKey;
 pub use persistence::get_saved_response;
 pub use persistence::save_response;
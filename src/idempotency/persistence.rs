@@ -2,6 +2,7 @@ use super::IdempotencyKey;
 use actix_web::body::to_bytes;
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
+use chrono::Utc;
 use sqlx::postgres::PgHasArrayType;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
@@ -19,10 +20,13 @@ impl PgHasArrayType for HeaderPairRecord {
     }
 }
 
+/// Responses saved more than `retention` ago have expired: they are
+/// never returned.
 pub async fn get_saved_response(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
     user_id: Uuid,
+    retention: chrono::Duration,
 ) -> Result<Option<HttpResponse>, anyhow::Error> {
     let saved_response = sqlx::query!(
         r#"
@@ -33,10 +37,12 @@ pub async fn get_saved_response(
         FROM idempotency
         WHERE
             user_id = $1 AND
-            idempotency_key = $2
+            idempotency_key = $2 AND
+            created_at > $3
         "#,
         user_id,
-        idempotency_key.as_ref()
+        idempotency_key.as_ref(),
+        Utc::now() - retention,
     )
     .fetch_optional(pool)
     .await?;
@@ -107,12 +113,18 @@ pub enum NextAction {
     ReturnSavedResponse(HttpResponse),
 }
 
+/// A key whose response was saved more than `retention` ago is processed
+/// again, as if it had never been seen.
 pub async fn try_processing(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
     user_id: Uuid,
+    retention: chrono::Duration,
 ) -> Result<NextAction, anyhow::Error> {
     let mut transaction = pool.begin().await?;
+    let expired_before = Utc::now() - retention;
+    // Concurrent requests with the same key wait for the first one to
+    // commit, then see a fresh `created_at` and update nothing.
     let n_inserted_rows = sqlx::query!(
         r#"
         INSERT INTO idempotency (
@@ -121,10 +133,17 @@ pub async fn try_processing(
             created_at
         )
         VALUES ($1, $2, now())
-        ON CONFLICT DO NOTHING
+        ON CONFLICT (user_id, idempotency_key) DO UPDATE
+        SET
+            created_at = now(),
+            response_status_code = NULL,
+            response_headers = NULL,
+            response_body = NULL
+        WHERE idempotency.created_at <= $3
         "#,
         user_id,
-        idempotency_key.as_ref()
+        idempotency_key.as_ref(),
+        expired_before,
     )
     .execute(&mut transaction)
     .await?
@@ -132,7 +151,7 @@ pub async fn try_processing(
     if n_inserted_rows > 0 {
         Ok(NextAction::StartProcessing(transaction))
     } else {
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+        let saved_response = get_saved_response(pool, idempotency_key, user_id, retention)
             .await?
             .ok_or_else(|| anyhow::anyhow!("we expected a saved response and didn't find it"))?;
         Ok(NextAction::ReturnSavedResponse(saved_response))
@@ -1,124 +1,547 @@
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
-use crate::{configuration::Settings, startup::get_connection_pool};
-use sqlx::{PgPool, Postgres, Transaction};
+use crate::configuration::{Settings, WorkerSettings};
+use crate::domain::{SegmentFilter, SubscriberEmail};
+use crate::email_client::{EmailClient, EmailHeader, OutgoingEmail};
+use crate::issue_template::{IssueContext, IssueTemplate, SubscriberContext, TemplateContext};
+use crate::rate_limiter::RateLimiter;
+use crate::routes::{add_tracking, archive_link, preferences_link, unsubscribe_link};
+use crate::startup::get_connection_pool;
+use secrecy::Secret;
+use sqlx::postgres::PgListener;
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::{field::display, Span};
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
 use uuid::Uuid;
 
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
-    let connection_pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
-    worker_loop(&connection_pool, email_client).await
+/// Number of delivery attempts before a task is moved to the dead-letter table.
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// Maximum number of deliveries claimed, and sent, at once.
+const DELIVERY_BATCH_SIZE: i64 = 100;
+/// Postgres channel notified whenever new tasks are enqueued.
+const NEW_TASKS_CHANNEL: &str = "issue_delivery_queue";
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let worker = Arc::new(Worker {
+        pool: get_connection_pool(&configuration.database),
+        rate_limiter: configuration.email_client.rate_limit.rate_limiter(),
+        email_client: configuration.email_client.client(),
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+        settings: configuration.worker,
+        new_tasks: Notify::new(),
+    });
+    let mut consumers = JoinSet::new();
+    consumers.spawn(listen_for_new_tasks(Arc::clone(&worker), shutdown.clone()));
+    for _ in 0..worker.settings.n_workers.max(1) {
+        consumers.spawn(worker_loop(Arc::clone(&worker), shutdown.clone()));
+    }
+    while let Some(outcome) = consumers.join_next().await {
+        outcome??;
+    }
+    Ok(())
 }
 
-async fn worker_loop(pool: &PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+/// Everything the delivery consumers share.
+struct Worker {
+    pool: PgPool,
+    email_client: EmailClient,
+    rate_limiter: RateLimiter,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    settings: WorkerSettings,
+    /// Woken up by `listen_for_new_tasks` when new tasks are enqueued.
+    new_tasks: Notify,
+}
+
+/// Wake up the idle consumers whenever `enqueue_delivery_tasks` notifies
+/// `NEW_TASKS_CHANNEL`. While the listener is disconnected the consumers
+/// fall back to polling the queue every `poll_interval`.
+async fn listen_for_new_tasks(
+    worker: Arc<Worker>,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let mut listener: Option<PgListener> = None;
+    while !shutdown.is_cancelled() {
+        let outcome = match listener.as_mut() {
+            Some(listener) => tokio::select! {
+                _ = shutdown.cancelled() => break,
+                outcome = listener.try_recv() => outcome,
+            },
+            None => match connect_listener(&worker.pool).await {
+                Ok(new_listener) => {
+                    listener = Some(new_listener);
+                    // Tasks may have been enqueued while we were not listening
+                    worker.new_tasks.notify_waiters();
+                    continue;
+                }
+                Err(e) => Err(e),
+            },
+        };
+        match outcome {
+            Ok(Some(_)) => {
+                worker.new_tasks.notify_waiters();
+                continue;
             }
-            Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(None) => {
+                tracing::warn!(
+                    "lost the connection listening for new delivery tasks, falling back to polling"
+                );
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "failed to listen for new delivery tasks, falling back to polling"
+                );
             }
-            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+        listener = None;
+        tokio::select! {
+            _ = shutdown.cancelled() => {},
+            _ = tokio::time::sleep(worker.settings.error_backoff()) => {},
         }
     }
+    Ok(())
+}
+
+async fn connect_listener(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(NEW_TASKS_CHANNEL).await?;
+    Ok(listener)
+}
+
+async fn worker_loop(
+    worker: Arc<Worker>,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    // Shutdown is only checked between batches: a batch that has been
+    // claimed is always sent and its transaction committed.
+    while !shutdown.is_cancelled() {
+        // Register interest before looking at the queue, so that tasks
+        // enqueued in the meantime are not missed.
+        let new_tasks = worker.new_tasks.notified();
+        tokio::pin!(new_tasks);
+        new_tasks.as_mut().enable();
+        let outcome = try_execute_task(
+            &worker.pool,
+            &worker.email_client,
+            &worker.rate_limiter,
+            &worker.base_url,
+            &worker.hmac_secret,
+            worker.settings.track_opens_and_clicks,
+        )
+        .await;
+        match outcome {
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+            // Polling is still needed: retried tasks become due without notice
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::select! {
+                _ = shutdown.cancelled() => {},
+                _ = new_tasks => {},
+                _ = tokio::time::sleep(worker.settings.poll_interval()) => {},
+            },
+            Ok(ExecutionOutcome::QuotaExhausted { retry_after }) => tokio::select! {
+                _ = shutdown.cancelled() => {},
+                _ = tokio::time::sleep(retry_after) => {},
+            },
+            Err(_) => tokio::select! {
+                _ = shutdown.cancelled() => {},
+                _ = tokio::time::sleep(worker.settings.error_backoff()) => {},
+            },
+        }
+    }
+    Ok(())
 }
 
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
+    /// Some tasks have been held back to respect the sending quotas.
+    QuotaExhausted {
+        retry_after: Duration,
+    },
 }
 
-#[tracing::instrument(
-    skip_all,
-    fields(
-        newsletter_issue_id=tracing::field::Empty,
-        subscriber_emai=tracing::field::Empty
-    ),
-    err
-)]
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    rate_limiter: &RateLimiter,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    track_opens_and_clicks: bool,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
-    if task.is_none() {
+    let Some((mut transaction, tasks)) = dequeue_tasks(pool, DELIVERY_BATCH_SIZE).await? else {
         return Ok(ExecutionOutcome::EmptyQueue);
-    }
+    };
+    Span::current().record("n_tasks", tasks.len());
 
-    let (transation, issue_id, email) = task.unwrap();
-
-    Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
-                .await
-            {
+    let recipients = get_confirmed_recipients(pool, &tasks).await?;
+    let mut issues = HashMap::new();
+    // Tasks waiting for the outcome of the batch, in the same order as `emails`
+    let mut in_flight = Vec::with_capacity(tasks.len());
+    let mut emails = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => email,
+            Err(e) => {
                 tracing::error!(
                     error.cause_chain = ?e,
                     error.message = %e,
-                    "failed to deliver issue to a confirmed subscriber: skipping.",
+                    subscriber_email = %task.subscriber_email,
+                    "skipping a confirmed subscriber: stored contact details are invalid"
                 );
+                record_delivery(&mut transaction, &task, DeliveryStatus::Skipped, Some(&e)).await?;
+                delete_task(&mut transaction, &task).await?;
+                continue;
             }
-        }
-        Err(e) => {
-            tracing::error!(
-                error.cause_chain = ?e,
-                error.message = %e,
-                "skipping a confirmed subscriber: stored contact details are invalid"
+        };
+        let recipient_key = (task.newsletter_issue_id, task.subscriber_email.clone());
+        let Some(recipient) = recipients.get(&recipient_key) else {
+            tracing::info!(
+                subscriber_email = %task.subscriber_email,
+                "skipping a subscriber who is no longer confirmed"
             );
+            record_delivery(
+                &mut transaction,
+                &task,
+                DeliveryStatus::Skipped,
+                Some("the subscriber is no longer confirmed"),
+            )
+            .await?;
+            delete_task(&mut transaction, &task).await?;
+            continue;
+        };
+        let issue = match issues.entry(task.newsletter_issue_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(get_issue(pool, task.newsletter_issue_id).await?),
+        };
+        let unsubscribe_link = unsubscribe_link(base_url, recipient.id, hmac_secret);
+        let preferences_link = preferences_link(base_url, recipient.id, hmac_secret);
+        let context = TemplateContext {
+            subscriber: SubscriberContext {
+                name: &recipient.name,
+                email: email.as_ref(),
+            },
+            issue: IssueContext {
+                title: &issue.title,
+            },
+            unsubscribe_url: &unsubscribe_link,
+            preferences_url: &preferences_link,
+        };
+        // Issues are validated when they are published: rendering can
+        // only fail for issues published before they were templates.
+        let rendered = match &issue.template {
+            Ok(template) => template.render(&context).map_err(|e| e.to_string()),
+            Err(e) => Err(e.clone()),
+        };
+        let rendered = match rendered {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                let e = format!("the issue could not be rendered: {e}");
+                tracing::error!(
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    "skipping a delivery"
+                );
+                record_delivery(&mut transaction, &task, DeliveryStatus::Skipped, Some(&e)).await?;
+                delete_task(&mut transaction, &task).await?;
+                continue;
+            }
+        };
+        let html_content = if track_opens_and_clicks {
+            add_tracking(
+                &rendered.html_content,
+                base_url,
+                task.newsletter_issue_id,
+                recipient.id,
+                hmac_secret,
+            )
+        } else {
+            rendered.html_content
+        };
+        let archive_link =
+            (!issue.is_private).then(|| archive_link(base_url, task.newsletter_issue_id));
+        emails.push(issue_email(
+            &issue.title,
+            &html_content,
+            &rendered.text_content,
+            email,
+            &unsubscribe_link,
+            &preferences_link,
+            archive_link.as_deref(),
+        ));
+        in_flight.push(task);
+    }
+
+    let grant = rate_limiter.acquire(pool, emails.len()).await?;
+    // The tasks held back are left untouched: they are released,
+    // as they were, when the transaction is committed.
+    emails.truncate(grant.granted);
+    in_flight.truncate(grant.granted);
+
+    if !emails.is_empty() {
+        match email_client.send_batch(&emails).await {
+            Ok(outcomes) => {
+                for (task, outcome) in in_flight.iter().zip(outcomes) {
+                    match outcome {
+                        Ok(()) => {
+                            record_delivery(&mut transaction, task, DeliveryStatus::Sent, None)
+                                .await?;
+                            delete_task(&mut transaction, task).await?;
+                        }
+                        Err(e) => handle_failed_delivery(&mut transaction, task, &e).await?,
+                    }
+                }
+            }
+            // Nothing went through: every recipient of the batch is retried
+            Err(e) => {
+                for task in &in_flight {
+                    handle_failed_delivery(&mut transaction, task, &e).await?;
+                }
+            }
         }
     }
-    delete_task(transation, issue_id, &email).await?;
-    Ok(ExecutionOutcome::TaskCompleted)
+    transaction.commit().await?;
+    match grant.retry_after {
+        Some(retry_after) => Ok(ExecutionOutcome::QuotaExhausted { retry_after }),
+        None => Ok(ExecutionOutcome::TaskCompleted),
+    }
+}
+
+/// Add the footer and the unsubscription headers to the content of an
+/// issue, once rendered for the recipient and tracked.
+fn issue_email(
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+    recipient: SubscriberEmail,
+    unsubscribe_link: &str,
+    preferences_link: &str,
+    archive_link: Option<&str>,
+) -> OutgoingEmail {
+    // Private issues have no web version
+    let (archive_html, archive_text) = match archive_link {
+        Some(archive_link) => (
+            format!("<a href=\"{}\">View in your browser</a> | ", archive_link),
+            format!("View in your browser: {}\n", archive_link),
+        ),
+        None => Default::default(),
+    };
+    let html_content = format!(
+        "{}<p>{}<a href=\"{}\">Manage your preferences</a> | <a href=\"{}\">Unsubscribe</a></p>",
+        html_content, archive_html, preferences_link, unsubscribe_link
+    );
+    let text_content = format!(
+        "{}\n\n{}Manage your preferences: {}\nUnsubscribe: {}",
+        text_content, archive_text, preferences_link, unsubscribe_link
+    );
+    // One-click unsubscription, as described in RFC 8058
+    let headers = vec![
+        EmailHeader {
+            name: "List-Unsubscribe".into(),
+            value: format!("<{}>", unsubscribe_link),
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        },
+    ];
+    OutgoingEmail {
+        recipient,
+        subject: title.to_owned(),
+        html_content,
+        text_content,
+        headers,
+    }
+}
+
+/// Retry the delivery later or, once it has run out of attempts,
+/// move it to the dead-letter table.
+async fn handle_failed_delivery(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    e: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    tracing::error!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        newsletter_issue_id = %task.newsletter_issue_id,
+        subscriber_email = %task.subscriber_email,
+        n_retries = task.n_retries,
+        "failed to deliver issue to a confirmed subscriber",
+    );
+    if task.n_retries + 1 >= MAX_DELIVERY_ATTEMPTS {
+        let error = e.to_string();
+        record_delivery(transaction, task, DeliveryStatus::Failed, Some(&error)).await?;
+        dead_letter_task(transaction, task, &error).await?;
+    } else {
+        retry_task(transaction, task).await?;
+    }
+    Ok(())
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+/// Queue a delivery for every confirmed subscriber of the lists the
+/// issue is sent to, restricted to the subscribers matching `segment`
+/// if there is one. Subscribers of several of the lists get a single email.
 #[tracing::instrument(skip_all)]
-async fn dequeue_task(
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    segment: Option<&SegmentFilter>,
+) -> Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT DISTINCT il.newsletter_issue_id, s.email
+        FROM subscriptions s
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        JOIN newsletter_issue_lists il ON il.list_id = ls.list_id
+        WHERE
+            s.status = 'confirmed' AND
+            ls.status = 'confirmed' AND
+            s.email NOT IN (SELECT email FROM email_suppressions) AND
+            il.newsletter_issue_id = "#,
+    );
+    query.push_bind(newsletter_issue_id);
+    if let Some(segment) = segment {
+        query.push(" AND (");
+        segment.push_sql(&mut query);
+        query.push(")");
+    }
+    query.build().execute(&mut *transaction).await?;
+    notify_new_tasks(transaction).await
+}
+
+/// The filter of the segment the issue is sent to, if any.
+#[tracing::instrument(skip(transaction))]
+pub async fn get_issue_segment(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<SegmentFilter>, anyhow::Error> {
+    let segment = sqlx::query!(
+        r#"
+        SELECT sg.filter
+        FROM newsletter_issues i
+        JOIN segments sg ON sg.segment_id = i.segment_id
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(transaction)
+    .await?;
+    segment
+        .map(|s| SegmentFilter::parse(&s.filter).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+/// Wake up the delivery workers once `transaction` is committed.
+pub async fn notify_new_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    // Notifications are only delivered on commit
+    sqlx::query!("SELECT pg_notify($1, '')", NEW_TASKS_CHANNEL)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
+
+/// Claim up to `n` tasks that are due. They stay locked, and invisible to
+/// other workers, until the returned transaction is committed or dropped.
+#[tracing::instrument(skip(pool))]
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    n: i64,
+) -> Result<Option<(PgTransaction, Vec<Task>)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let tasks = sqlx::query_as!(
+        Task,
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT newsletter_issue_id, subscriber_email, n_retries
         FROM issue_delivery_queue
+        WHERE execute_after <= now()
         FOR UPDATE
         SKIP LOCKED
-        LIMIT 1
+        LIMIT $1
         "#,
+        n,
     )
-    .fetch_optional(&mut transaction)
+    .fetch_all(&mut transaction)
     .await?;
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
+    if tasks.is_empty() {
+        return Ok(None);
     }
+    Ok(Some((transaction, tasks)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(transaction: &mut PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
 
+/// Put the task back in the queue, to be picked up again once
+/// its exponential backoff delay has elapsed.
 #[tracing::instrument(skip_all)]
-async fn delete_task(
-    mut transaction: PgTransaction,
-    issue_id: Uuid,
-    email: &str,
+async fn retry_task(transaction: &mut PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    let delay = retry_delay(task.n_retries);
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Move the task out of the queue and into the dead-letter table,
+/// where it can be inspected and re-enqueued by an admin.
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    error: &str,
 ) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"
@@ -127,27 +550,107 @@ async fn delete_task(
             newsletter_issue_id = $1 AND
             subscriber_email = $2
         "#,
-        issue_id,
-        email,
+        task.newsletter_issue_id,
+        task.subscriber_email,
     )
-    .execute(&mut transaction)
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_retries = EXCLUDED.n_retries,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries + 1,
+        error,
+    )
+    .execute(transaction)
     .await?;
-    transaction.commit().await?;
     Ok(())
 }
 
+enum DeliveryStatus {
+    Sent,
+    Failed,
+    Skipped,
+}
+
+impl DeliveryStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// Keep track of the outcome of a delivery, to report on
+/// the progress of each newsletter issue.
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    status: DeliveryStatus,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            error,
+            recorded_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            status = EXCLUDED.status,
+            error = EXCLUDED.error,
+            recorded_at = EXCLUDED.recorded_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status.as_str(),
+        error,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// The delay before the next delivery attempt doubles
+/// after every failure, up to `MAX_RETRY_DELAY`.
+fn retry_delay(n_retries: i16) -> Duration {
+    let factor = 2u32.saturating_pow(n_retries.max(0) as u32);
+    BASE_RETRY_DELAY.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
 struct NewsletterIssue {
     title: String,
-    text_content: String,
-    html_content: String,
+    is_private: bool,
+    /// Parsed once for all the recipients of the batch
+    template: Result<IssueTemplate, String>,
 }
 
 #[tracing::instrument(skip_all)]
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
-    let issue = sqlx::query_as!(
-        NewsletterIssue,
+    let issue = sqlx::query!(
         r#"
-        SELECT title, text_content, html_content
+        SELECT title, text_content, html_content, is_private
         FROM newsletter_issues
         WHERE
             newsletter_issue_id = $1
@@ -156,5 +659,83 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     )
     .fetch_one(pool)
     .await?;
-    Ok(issue)
+    Ok(NewsletterIssue {
+        title: issue.title,
+        is_private: issue.is_private,
+        template: IssueTemplate::parse(&issue.html_content, &issue.text_content)
+            .map_err(|e| e.to_string()),
+    })
+}
+
+struct ConfirmedRecipient {
+    id: Uuid,
+    name: String,
+}
+
+/// Subscribers can unsubscribe, leave a list (or be removed) after an
+/// issue has been enqueued for them: they must not receive it.
+/// Returns the recipients that are still confirmed, on at least one of the
+/// lists of the issue, by issue and email.
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_recipients(
+    pool: &PgPool,
+    tasks: &[Task],
+) -> Result<HashMap<(Uuid, String), ConfirmedRecipient>, anyhow::Error> {
+    let (issue_ids, emails): (Vec<_>, Vec<_>) = tasks
+        .iter()
+        .map(|t| (t.newsletter_issue_id, t.subscriber_email.clone()))
+        .unzip();
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT
+            t.newsletter_issue_id AS "newsletter_issue_id!",
+            s.id,
+            s.email,
+            s.name
+        FROM UNNEST($1::uuid[], $2::text[]) AS t(newsletter_issue_id, email)
+        JOIN subscriptions s ON s.email = t.email
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        JOIN newsletter_issue_lists il
+            ON il.list_id = ls.list_id AND il.newsletter_issue_id = t.newsletter_issue_id
+        WHERE s.status = 'confirmed' AND ls.status = 'confirmed'
+        "#,
+        &issue_ids[..],
+        &emails[..],
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            (
+                (r.newsletter_issue_id, r.email),
+                ConfirmedRecipient {
+                    id: r.id,
+                    name: r.name,
+                },
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, BASE_RETRY_DELAY, MAX_RETRY_DELAY};
+
+    #[test]
+    fn the_first_retry_waits_for_the_base_delay() {
+        assert_eq!(retry_delay(0), BASE_RETRY_DELAY);
+    }
+
+    #[test]
+    fn the_retry_delay_doubles_after_every_failure() {
+        assert_eq!(retry_delay(1), BASE_RETRY_DELAY * 2);
+        assert_eq!(retry_delay(3), BASE_RETRY_DELAY * 8);
+    }
+
+    #[test]
+    fn the_retry_delay_is_capped() {
+        assert_eq!(retry_delay(20), MAX_RETRY_DELAY);
+        assert!(retry_delay(i16::MAX) <= MAX_RETRY_DELAY);
+    }
 }
@@ -0,0 +1,112 @@
+use crate::issue_delivery_worker::{enqueue_delivery_tasks, get_issue_segment};
+use crate::{configuration::Settings, startup::get_connection_pool};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+pub async fn run_scheduler_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(
+        &connection_pool,
+        configuration.worker.poll_interval(),
+        shutdown,
+    )
+    .await
+}
+
+async fn scheduler_loop(
+    pool: &PgPool,
+    poll_interval: Duration,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        // Errors are already logged by `publish_due_issues`; try again on the next tick.
+        let _ = publish_due_issues(pool).await;
+        tokio::select! {
+            _ = shutdown.cancelled() => {},
+            _ = tokio::time::sleep(poll_interval) => {},
+        }
+    }
+    Ok(())
+}
+
+/// Publish every scheduled issue whose time has come and fan out its
+/// delivery tasks. Returns the number of published issues.
+///
+/// Each issue is published in its own transaction: one that fails is
+/// logged and left scheduled, to be retried on the next tick, without
+/// holding back the others.
+#[tracing::instrument(skip_all, err)]
+pub async fn publish_due_issues(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let due_issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_for <= now()
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut n_published = 0;
+    for issue in &due_issues {
+        match publish_scheduled_issue(pool, issue.newsletter_issue_id).await {
+            Ok(true) => {
+                n_published += 1;
+                tracing::info!(
+                    newsletter_issue_id = %issue.newsletter_issue_id,
+                    "scheduled issue published"
+                );
+            }
+            // Published or cancelled in the meantime
+            Ok(false) => {}
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %issue.newsletter_issue_id,
+                    "failed to publish a scheduled issue"
+                );
+            }
+        }
+    }
+    Ok(n_published)
+}
+
+/// Returns `false` if the issue is no longer due or is being published
+/// by another instance.
+async fn publish_scheduled_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = now()
+        WHERE newsletter_issue_id = (
+            SELECT newsletter_issue_id
+            FROM newsletter_issues
+            WHERE newsletter_issue_id = $1
+                AND status = 'scheduled'
+                AND scheduled_for <= now()
+            FOR UPDATE
+            SKIP LOCKED
+        )
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        return Ok(false);
+    }
+    let segment = get_issue_segment(&mut transaction, newsletter_issue_id).await?;
+    enqueue_delivery_tasks(&mut transaction, newsletter_issue_id, segment.as_ref()).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
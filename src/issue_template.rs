@@ -0,0 +1,187 @@
+use minijinja::{Environment, UndefinedBehavior, Value};
+use serde::Serialize;
+
+const HTML_TEMPLATE: &str = "issue.html";
+const TEXT_TEMPLATE: &str = "issue.txt";
+
+/// The content of a newsletter issue: templates rendered for every
+/// recipient, with the following variables:
+///
+/// - `subscriber.name` and `subscriber.email`;
+/// - `issue.title`;
+/// - `unsubscribe_url` and `preferences_url`.
+///
+/// Using any other variable is an error. Values are HTML-escaped in the
+/// HTML content, left as they are in the plain text one.
+pub struct IssueTemplate {
+    env: Environment<'static>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TemplateContext<'a> {
+    pub subscriber: SubscriberContext<'a>,
+    pub issue: IssueContext<'a>,
+    #[serde(serialize_with = "safe_url")]
+    pub unsubscribe_url: &'a str,
+    #[serde(serialize_with = "safe_url")]
+    pub preferences_url: &'a str,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberContext<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueContext<'a> {
+    pub title: &'a str,
+}
+
+pub struct RenderedIssue {
+    pub html_content: String,
+    pub text_content: String,
+}
+
+impl IssueTemplate {
+    /// Fails on syntax errors.
+    pub fn parse(html_content: &str, text_content: &str) -> Result<Self, minijinja::Error> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        // Auto-escaping is picked from the extension of the template name
+        env.add_template_owned(HTML_TEMPLATE, html_content.to_owned())?;
+        env.add_template_owned(TEXT_TEMPLATE, text_content.to_owned())?;
+        Ok(Self { env })
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> Result<RenderedIssue, minijinja::Error> {
+        Ok(RenderedIssue {
+            html_content: self.env.get_template(HTML_TEMPLATE)?.render(context)?,
+            text_content: self.env.get_template(TEXT_TEMPLATE)?.render(context)?,
+        })
+    }
+
+    /// Check the content of an issue before it is published: syntax errors
+    /// and unknown variables are caught by rendering it with sample values.
+    pub fn validate(title: &str, html_content: &str, text_content: &str) -> Result<(), String> {
+        IssueTemplate::parse(html_content, text_content)
+            .and_then(|template| template.render(&TemplateContext::sample(title)))
+            .map(|_| ())
+            .map_err(|e| format!("the issue content is not a valid template: {e}"))
+    }
+}
+
+impl TemplateContext<'_> {
+    /// Placeholder values, for previews and test emails.
+    pub fn sample(title: &str) -> TemplateContext<'_> {
+        TemplateContext {
+            subscriber: SubscriberContext {
+                name: "Subscriber name",
+                email: "subscriber@example.com",
+            },
+            issue: IssueContext { title },
+            unsubscribe_url: "#unsubscribe",
+            preferences_url: "#preferences",
+        }
+    }
+
+    /// Values for the web version of an issue, which is not read by any
+    /// subscriber in particular.
+    pub fn archive(title: &str) -> TemplateContext<'_> {
+        TemplateContext {
+            subscriber: SubscriberContext {
+                name: "reader",
+                email: "",
+            },
+            issue: IssueContext { title },
+            unsubscribe_url: "#",
+            preferences_url: "#",
+        }
+    }
+}
+
+/// Our own links are not escaped, so that they can be used as they are
+/// in `href` attributes: they never contain characters to escape.
+fn safe_url<S: serde::Serializer>(url: &&str, serializer: S) -> Result<S::Ok, S::Error> {
+    Value::from_safe_string((*url).to_owned()).serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IssueContext, IssueTemplate, SubscriberContext, TemplateContext};
+    use claims::{assert_err, assert_ok};
+
+    fn context<'a>(name: &'a str) -> TemplateContext<'a> {
+        TemplateContext {
+            subscriber: SubscriberContext {
+                name,
+                email: "ursula@example.com",
+            },
+            issue: IssueContext { title: "Issue #1" },
+            unsubscribe_url: "https://example.com/unsubscribe?token=a",
+            preferences_url: "https://example.com/preferences",
+        }
+    }
+
+    #[test]
+    fn variables_are_rendered_for_the_recipient() {
+        let template = IssueTemplate::parse(
+            "<p>Hi {{ subscriber.name }}, welcome to {{ issue.title }}</p>",
+            "Hi {{ subscriber.name }}: {{ unsubscribe_url }}",
+        )
+        .unwrap();
+        let rendered = template.render(&context("Ursula")).unwrap();
+        assert_eq!(
+            rendered.html_content,
+            "<p>Hi Ursula, welcome to Issue #1</p>"
+        );
+        assert_eq!(
+            rendered.text_content,
+            "Hi Ursula: https://example.com/unsubscribe?token=a"
+        );
+    }
+
+    #[test]
+    fn values_are_only_escaped_in_the_html_content() {
+        let template = IssueTemplate::parse(
+            "{{ subscriber.name }} <a href=\"{{ unsubscribe_url }}\">",
+            "{{ subscriber.name }}",
+        )
+        .unwrap();
+        let rendered = template.render(&context("Ursula & Co")).unwrap();
+        assert_eq!(
+            rendered.html_content,
+            "Ursula &amp; Co <a href=\"https://example.com/unsubscribe?token=a\">"
+        );
+        assert_eq!(rendered.text_content, "Ursula & Co");
+    }
+
+    #[test]
+    fn syntax_errors_are_rejected() {
+        assert_err!(IssueTemplate::validate("Title", "{{ subscriber.name", ""));
+        assert_err!(IssueTemplate::validate("Title", "", "{% if %}"));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        assert_err!(IssueTemplate::validate(
+            "Title",
+            "{{ subscriber.nmae }}",
+            ""
+        ));
+        assert_err!(IssueTemplate::validate(
+            "Title",
+            "",
+            "{{ unsubscribe_link }}"
+        ));
+    }
+
+    #[test]
+    fn content_without_variables_is_valid() {
+        assert_ok!(IssueTemplate::validate(
+            "Title",
+            "<p>Newsletter body as HTML</p>",
+            "Newsletter body as plain text"
+        ));
+    }
+}
@@ -4,8 +4,14 @@ pub mod domain;
 pub mod email_client;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod issue_template;
+pub mod markdown;
+pub mod rate_limiter;
 pub mod routes;
 pub mod session_state;
+pub mod shutdown;
 pub mod startup;
+pub mod subscription_cleanup;
 pub mod telemetry;
 pub mod utils;
@@ -1,8 +1,14 @@
 use std::fmt::{Debug, Display};
+use std::future::Future;
 use tokio::task::JoinError;
+use tokio_util::sync::CancellationToken;
 use zero2prod::configuration::get_configuration;
+use zero2prod::idempotency::run_expiry_until_stopped;
 use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::issue_scheduler::run_scheduler_until_stopped;
+use zero2prod::shutdown::cancel_on_shutdown_signal;
 use zero2prod::startup::Application;
+use zero2prod::subscription_cleanup::run_cleanup_until_stopped;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 #[tokio::main]
@@ -12,17 +18,54 @@ async fn main() -> anyhow::Result<()> {
 
     let configuration = get_configuration().expect("failed to read configuration");
     let application = Application::build(configuration.clone()).await?;
-    let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_shutdown_signal(shutdown.clone()));
 
-    tokio::select! {
-        o = application_task => report_exit("API", o),
-        o = worker_task => report_exit("Background worker", o),
-    };
+    // Wait for every task to wind down, so that in-flight requests
+    // and deliveries are not cut short.
+    tokio::join!(
+        run_task(
+            "API",
+            application.run_until_stopped(shutdown.clone()),
+            shutdown.clone(),
+        ),
+        run_task(
+            "Background worker",
+            run_worker_until_stopped(configuration.clone(), shutdown.clone()),
+            shutdown.clone(),
+        ),
+        run_task(
+            "Issue scheduler",
+            run_scheduler_until_stopped(configuration.clone(), shutdown.clone()),
+            shutdown.clone(),
+        ),
+        run_task(
+            "Subscription cleanup",
+            run_cleanup_until_stopped(configuration.clone(), shutdown.clone()),
+            shutdown.clone(),
+        ),
+        run_task(
+            "Idempotency key expiry",
+            run_expiry_until_stopped(configuration, shutdown.clone()),
+            shutdown,
+        ),
+    );
 
     Ok(())
 }
 
+/// Run `task` to completion. Whatever its outcome, the other tasks are then
+/// asked to stop: the application does not keep running half-broken.
+async fn run_task<F, E>(task_name: &str, task: F, shutdown: CancellationToken)
+where
+    F: Future<Output = Result<(), E>> + Send + 'static,
+    E: Debug + Display + Send + 'static,
+{
+    let outcome = tokio::spawn(task).await;
+    report_exit(task_name, outcome);
+    shutdown.cancel();
+}
+
 fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
     match outcome {
         Ok(Ok(())) => {
@@ -0,0 +1,271 @@
+use htmlescape::encode_minimal;
+use pulldown_cmark::{html, Event, HeadingLevel, Parser, Tag, TagEnd};
+use std::fmt::Write;
+
+/// The HTML and plain text contents of an issue written in Markdown.
+///
+/// The HTML is sanitised: raw HTML in the source is shown as text and
+/// links or images with a scheme other than `http`, `https` or `mailto`
+/// are dropped. Template variables (see [`crate::issue_template`]) are
+/// left untouched, including in link destinations.
+pub struct MarkdownContent {
+    pub html_content: String,
+    pub text_content: String,
+}
+
+impl MarkdownContent {
+    pub fn render(markdown: &str) -> Self {
+        Self {
+            html_content: render_html(markdown),
+            text_content: render_text(markdown),
+        }
+    }
+}
+
+fn render_html(markdown: &str) -> String {
+    // Whether each link or image being rendered has been kept
+    let mut kept = Vec::new();
+    let events = Parser::new(markdown).filter_map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Some(Event::Text(html)),
+        // Links are written by hand: the renderer would percent-encode
+        // the braces of a template variable.
+        Event::Start(Tag::Link {
+            dest_url, title, ..
+        }) => {
+            let is_safe = is_safe_url(&dest_url);
+            kept.push(is_safe);
+            is_safe.then(|| {
+                let mut a = format!(r#"<a href="{}""#, encode_href(&dest_url));
+                if !title.is_empty() {
+                    write!(a, r#" title="{}""#, encode_href(&title)).unwrap();
+                }
+                a.push('>');
+                Event::InlineHtml(a.into())
+            })
+        }
+        Event::End(TagEnd::Link) => kept
+            .pop()
+            .unwrap_or_default()
+            .then(|| Event::InlineHtml("</a>".into())),
+        // The alternative text of a dropped image is shown instead
+        Event::Start(Tag::Image { ref dest_url, .. }) => {
+            let is_safe = is_safe_url(dest_url);
+            kept.push(is_safe);
+            is_safe.then_some(event)
+        }
+        Event::End(TagEnd::Image) => kept.pop().unwrap_or_default().then_some(event),
+        event => Some(event),
+    });
+    let mut html_content = String::new();
+    html::push_html(&mut html_content, events);
+    html_content
+}
+
+/// Only a few schemes are allowed. Relative URLs, such as template
+/// variables, are safe.
+fn is_safe_url(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once(':') else {
+        return true;
+    };
+    // Browsers ignore whitespace in schemes, e.g. `java script:`
+    let scheme: String = scheme
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    if !scheme
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    {
+        // Not a scheme: a colon in a relative URL
+        return true;
+    }
+    ["http", "https", "mailto"]
+        .iter()
+        .any(|safe| scheme.eq_ignore_ascii_case(safe))
+}
+
+/// Unlike `htmlescape::encode_attribute`, braces are kept.
+fn encode_href(value: &str) -> String {
+    encode_minimal(value).replace('"', "&quot;")
+}
+
+/// A readable plain text version: headings are underlined, list items
+/// are bulleted and links are followed by their destination.
+fn render_text(markdown: &str) -> String {
+    let mut text = String::new();
+    // Where the headings, block quotes and code blocks being rendered
+    // start in `text`
+    let mut starts = Vec::new();
+    // The numbers of the ordered lists being rendered, `None` for bullets
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    // The destinations of the links and images being rendered
+    let mut destinations = Vec::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading { .. }) | Event::Start(Tag::BlockQuote(_)) => {
+                starts.push(text.len())
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let start = starts.pop().unwrap_or_default();
+                let width = text[start..].chars().count();
+                match level {
+                    HeadingLevel::H1 => write!(text, "\n{}", "=".repeat(width)).unwrap(),
+                    HeadingLevel::H2 => write!(text, "\n{}", "-".repeat(width)).unwrap(),
+                    _ => {}
+                }
+                text.push_str("\n\n");
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                let start = starts.pop().unwrap_or_default();
+                let quote: String = text[start..]
+                    .trim_end()
+                    .lines()
+                    .map(|line| match line {
+                        "" => ">\n".to_owned(),
+                        line => format!("> {line}\n"),
+                    })
+                    .collect();
+                text.truncate(start);
+                text.push_str(&quote);
+                text.push('\n');
+            }
+            Event::End(TagEnd::Paragraph) => text.push_str("\n\n"),
+            // The lines of HTML blocks end with a new line already
+            Event::End(TagEnd::HtmlBlock) => text.push('\n'),
+            Event::Start(Tag::List(first_number)) => {
+                if !lists.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                lists.push(first_number);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        write!(text, "{number}. ").unwrap();
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::End(TagEnd::Item) => {
+                // Items of loose lists are paragraphs
+                let trimmed = text.trim_end_matches('\n').len();
+                text.truncate(trimmed);
+                text.push('\n');
+            }
+            Event::Start(Tag::CodeBlock(_)) => starts.push(text.len()),
+            Event::End(TagEnd::CodeBlock) => {
+                let start = starts.pop().unwrap_or_default();
+                let code: String = text[start..]
+                    .lines()
+                    .map(|line| format!("    {line}\n"))
+                    .collect();
+                text.truncate(start);
+                text.push_str(&code);
+                text.push('\n');
+            }
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => {
+                destinations.push((text.len(), dest_url))
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some((start, dest_url)) = destinations.pop() {
+                    if text[start..] != *dest_url {
+                        write!(text, " ({dest_url})").unwrap();
+                    }
+                }
+            }
+            Event::Text(t)
+            | Event::Code(t)
+            | Event::Html(t)
+            | Event::InlineHtml(t)
+            | Event::InlineMath(t)
+            | Event::DisplayMath(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("----\n\n"),
+            _ => {}
+        }
+    }
+    let trimmed = text.trim_end().len();
+    text.truncate(trimmed);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MarkdownContent;
+
+    #[test]
+    fn markdown_is_rendered_as_html_and_plain_text() {
+        let content = MarkdownContent::render(
+            "# Issue #1\n\nHi **{{ subscriber.name }}**, read [the post](https://example.com).\n\n\
+            - one\n- two\n\n> quoted\n",
+        );
+        assert_eq!(
+            content.html_content,
+            "<h1>Issue #1</h1>\n\
+            <p>Hi <strong>{{ subscriber.name }}</strong>, read <a href=\"https://example.com\">the post</a>.</p>\n\
+            <ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+            <blockquote>\n<p>quoted</p>\n</blockquote>\n"
+        );
+        assert_eq!(
+            content.text_content,
+            "Issue #1\n========\n\n\
+            Hi {{ subscriber.name }}, read the post (https://example.com).\n\n\
+            - one\n- two\n\n\
+            > quoted"
+        );
+    }
+
+    #[test]
+    fn template_variables_are_kept_in_links() {
+        let content = MarkdownContent::render("[Unsubscribe]({{unsubscribe_url}})");
+        assert_eq!(
+            content.html_content,
+            "<p><a href=\"{{unsubscribe_url}}\">Unsubscribe</a></p>\n"
+        );
+        assert_eq!(content.text_content, "Unsubscribe ({{unsubscribe_url}})");
+    }
+
+    #[test]
+    fn raw_html_is_escaped() {
+        let content = MarkdownContent::render("<script>alert(1)</script>\n\nHi <b>there</b>");
+        assert_eq!(
+            content.text_content,
+            "<script>alert(1)</script>\n\nHi <b>there</b>"
+        );
+        assert!(!content.html_content.contains("<script>"));
+        assert!(content.html_content.contains("&lt;script&gt;"));
+        assert!(content.html_content.contains("Hi &lt;b&gt;there&lt;/b&gt;"));
+    }
+
+    #[test]
+    fn links_with_unsafe_schemes_are_dropped() {
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "data:text/html,x",
+        ] {
+            let content = MarkdownContent::render(&format!("[click](<{url}>) ![image](<{url}>)"));
+            assert_eq!(content.html_content, "<p>click image</p>\n", "{url}");
+        }
+        let content = MarkdownContent::render("[mail](mailto:a@example.com) [page](/archive)");
+        assert_eq!(
+            content.html_content,
+            "<p><a href=\"mailto:a@example.com\">mail</a> <a href=\"/archive\">page</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn nested_and_ordered_lists_are_indented() {
+        let content = MarkdownContent::render("1. first\n   - a\n   - b\n2. second\n");
+        assert_eq!(content.text_content, "1. first\n  - a\n  - b\n2. second");
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+/// A sending quota enforced by the email provider,
+/// e.g. at most 50 emails per second.
+#[derive(Clone, Debug)]
+struct Quota {
+    name: &'static str,
+    limit: NonZeroU32,
+    period: Duration,
+}
+
+impl Quota {
+    /// Tokens regained per second.
+    fn refill_rate(&self) -> f64 {
+        f64::from(self.limit.get()) / self.period.as_secs_f64()
+    }
+
+    /// Tokens available after `elapsed` time, starting from `tokens`.
+    fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        (tokens + elapsed.as_secs_f64() * self.refill_rate()).min(f64::from(self.limit.get()))
+    }
+}
+
+/// Token-bucket limiter for outbound emails. One token is spent per email.
+///
+/// The buckets are stored in Postgres, so that every delivery worker,
+/// in this process or another one, draws from the same quotas.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    quotas: Vec<Quota>,
+}
+
+/// How many of the requested emails can be sent right away.
+#[derive(Debug)]
+pub struct Grant {
+    pub granted: usize,
+    /// When some emails were held back, how long to wait
+    /// before a token is available again.
+    pub retry_after: Option<Duration>,
+}
+
+pub struct QuotaUsage {
+    pub name: &'static str,
+    pub limit: u32,
+    pub used: u32,
+}
+
+impl RateLimiter {
+    pub fn new(
+        per_second: Option<NonZeroU32>,
+        per_hour: Option<NonZeroU32>,
+        per_day: Option<NonZeroU32>,
+    ) -> Self {
+        let quotas = [
+            ("per second", per_second, Duration::from_secs(1)),
+            ("per hour", per_hour, Duration::from_secs(60 * 60)),
+            ("per day", per_day, Duration::from_secs(24 * 60 * 60)),
+        ]
+        .into_iter()
+        .filter_map(|(name, limit, period)| {
+            limit.map(|limit| Quota {
+                name,
+                limit,
+                period,
+            })
+        })
+        .collect();
+        Self { quotas }
+    }
+
+    /// Spend one token per email, for up to `wanted` emails.
+    #[tracing::instrument(skip(self, pool))]
+    pub async fn acquire(&self, pool: &PgPool, wanted: usize) -> Result<Grant, anyhow::Error> {
+        if self.quotas.is_empty() || wanted == 0 {
+            return Ok(Grant {
+                granted: wanted,
+                retry_after: None,
+            });
+        }
+        let mut transaction = pool.begin().await?;
+        let buckets = self.lock_buckets(&mut transaction).await?;
+        let granted = buckets
+            .iter()
+            .map(|(_, available, _)| available.floor() as usize)
+            .fold(wanted, usize::min);
+        let mut retry_after = None;
+        for (quota, available, now) in &buckets {
+            let tokens = available - granted as f64;
+            sqlx::query!(
+                r#"
+                UPDATE email_rate_limits
+                SET tokens = $2, refilled_at = $3
+                WHERE quota_name = $1
+                "#,
+                quota.name,
+                tokens,
+                now,
+            )
+            .execute(&mut transaction)
+            .await?;
+            if granted < wanted && tokens < 1.0 {
+                let wait = Duration::from_secs_f64((1.0 - tokens) / quota.refill_rate());
+                retry_after = retry_after.max(Some(wait));
+            }
+        }
+        transaction.commit().await?;
+        if granted < wanted {
+            tracing::info!(granted, "sending quota exhausted, holding back emails");
+        }
+        Ok(Grant {
+            granted,
+            retry_after,
+        })
+    }
+
+    /// How many emails have been sent within each quota.
+    ///
+    /// The buckets are only read, without locking them: the workers are
+    /// never held up by the dashboard.
+    pub async fn usage(&self, pool: &PgPool) -> Result<Vec<QuotaUsage>, anyhow::Error> {
+        if self.quotas.is_empty() {
+            return Ok(Vec::new());
+        }
+        let names: Vec<_> = self.quotas.iter().map(|q| q.name.to_owned()).collect();
+        let buckets: HashMap<_, _> = sqlx::query!(
+            r#"
+            SELECT quota_name, tokens, refilled_at, now() AS "now!"
+            FROM email_rate_limits
+            WHERE quota_name = ANY($1)
+            "#,
+            &names[..],
+        )
+        .fetch_all(pool)
+        .await
+        .context("failed to read the rate limiting buckets")?
+        .into_iter()
+        .map(|b| {
+            let elapsed = (b.now - b.refilled_at).to_std().unwrap_or_default();
+            (b.quota_name, (b.tokens, elapsed))
+        })
+        .collect();
+        Ok(self
+            .quotas
+            .iter()
+            .map(|quota| {
+                let limit = quota.limit.get();
+                // Buckets are created full, on the first send
+                let available = buckets
+                    .get(quota.name)
+                    .map_or(f64::from(limit), |&(tokens, elapsed)| {
+                        quota.refill(tokens, elapsed)
+                    });
+                QuotaUsage {
+                    name: quota.name,
+                    limit,
+                    used: (f64::from(limit) - available).ceil().max(0.0) as u32,
+                }
+            })
+            .collect())
+    }
+
+    /// Lock the bucket of every quota, creating the missing ones full.
+    /// Returns the tokens available in each bucket as of the database's `now()`.
+    async fn lock_buckets(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Vec<(&Quota, f64, DateTime<Utc>)>, anyhow::Error> {
+        let mut buckets = Vec::with_capacity(self.quotas.len());
+        // Always lock the buckets in the same order to avoid deadlocks
+        for quota in &self.quotas {
+            sqlx::query!(
+                r#"
+                INSERT INTO email_rate_limits (quota_name, tokens, refilled_at)
+                VALUES ($1, $2, now())
+                ON CONFLICT DO NOTHING
+                "#,
+                quota.name,
+                f64::from(quota.limit.get()),
+            )
+            .execute(&mut *transaction)
+            .await?;
+            let bucket = sqlx::query!(
+                r#"
+                SELECT tokens, refilled_at, now() AS "now!"
+                FROM email_rate_limits
+                WHERE quota_name = $1
+                FOR UPDATE
+                "#,
+                quota.name,
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .context("failed to lock a rate limiting bucket")?;
+            let elapsed = (bucket.now - bucket.refilled_at)
+                .to_std()
+                .unwrap_or_default();
+            buckets.push((quota, quota.refill(bucket.tokens, elapsed), bucket.now));
+        }
+        Ok(buckets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quota;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    fn per_minute(limit: u32) -> Quota {
+        Quota {
+            name: "per minute",
+            limit: NonZeroU32::new(limit).unwrap(),
+            period: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn buckets_refill_proportionally_to_the_elapsed_time() {
+        let quota = per_minute(60);
+        assert_eq!(quota.refill(0.0, Duration::from_secs(10)), 10.0);
+        assert_eq!(quota.refill(5.5, Duration::from_millis(500)), 6.0);
+    }
+
+    #[test]
+    fn buckets_never_hold_more_than_the_limit() {
+        let quota = per_minute(60);
+        assert_eq!(quota.refill(50.0, Duration::from_secs(3600)), 60.0);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::authentication::{generate_api_token, hash_api_token, UserId};
+use crate::utils::{e500, see_other};
+use actix_web::http::header::{CacheControl, CacheDirective, ContentType};
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct ApiToken {
+    token_id: Uuid,
+    name: String,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ApiTokenFormData {
+    name: String,
+}
+
+pub async fn api_tokens(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let user_id = user_id.into_inner();
+    let mut tokens_html = String::new();
+    for token in get_api_tokens(&pool, *user_id).await.map_err(e500)? {
+        writeln!(
+            tokens_html,
+            r#"<tr><td>{}</td><td>{}</td><td>{}</td><td><form action="/admin/api_tokens/{}/revoke" method="post"><button type="submit">Revoke</button></form></td></tr>"#,
+            encode_minimal(&token.name),
+            token.created_at.format("%Y-%m-%d %H:%M UTC"),
+            token
+                .last_used_at
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_else(|| "never".into()),
+            token.token_id,
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API tokens</title>
+</head>
+<body>
+    {msg_html}
+    <p>API tokens authenticate requests to <code>/api/v1</code>, sent with an
+    <code>Authorization: Bearer &lt;token&gt;</code> header.</p>
+    <table>
+        <tr><th>Name</th><th>Created</th><th>Last used</th><th></th></tr>
+        {tokens_html}
+    </table>
+    <h2>New token</h2>
+    <form action="/admin/api_tokens" method="post">
+        <p><label>Name
+            <input type="text" name="name" placeholder="What the token is used for">
+        </label></p>
+        <button type="submit">Create token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "create an API token", skip_all, fields(user_id=%&*user_id))]
+pub async fn create_api_token(
+    form: web::Form<ApiTokenFormData>,
+    pool: web::Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("the token must have a name").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    let token = generate_api_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, created_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        *user_id,
+        name,
+        hash_api_token(&token),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to store the API token")
+    .map_err(e500)?;
+    // Only the hash is stored: this is the only time the token is shown.
+    // It is rendered right away rather than carried to the next page in a
+    // flash message, which would store it in a cookie.
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API token created</title>
+</head>
+<body>
+    <p>The token {} has been created. Copy it now, it will not be shown again:</p>
+    <p><code>{token}</code></p>
+    <p><a href="/admin/api_tokens">&lt;- Back</a></p>
+</body>
+</html>"#,
+            encode_minimal(name),
+        )))
+}
+
+#[tracing::instrument(name = "revoke an API token", skip(pool, user_id))]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let n_deleted_rows = sqlx::query!(
+        "DELETE FROM api_tokens WHERE token_id = $1 AND user_id = $2",
+        token_id.into_inner(),
+        *user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to revoke the API token")
+    .map_err(e500)?
+    .rows_affected();
+    if n_deleted_rows == 0 {
+        FlashMessage::error("the token does not exist").send();
+    } else {
+        FlashMessage::info("the token has been revoked").send();
+    }
+    Ok(see_other("/admin/api_tokens"))
+}
+
+#[tracing::instrument(name = "get API tokens", skip(pool))]
+async fn get_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT token_id, name, created_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the API tokens")?;
+    Ok(tokens)
+}
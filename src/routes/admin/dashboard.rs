@@ -1,3 +1,4 @@
+use crate::rate_limiter::RateLimiter;
 use crate::session_state::TypedSession;
 use crate::utils::e500;
 use actix_web::http::header::{ContentType, LOCATION};
@@ -9,6 +10,7 @@ use uuid::Uuid;
 pub async fn admin_dashboard(
     session: TypedSession,
     pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
         get_username(user_id, &pool).await.map_err(e500)?
@@ -17,6 +19,21 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
+    let quota_usage = rate_limiter.usage(&pool).await.map_err(e500)?;
+    let quota_usage = if quota_usage.is_empty() {
+        "<p>Outbound emails are not rate limited.</p>".to_string()
+    } else {
+        let items: String = quota_usage
+            .iter()
+            .map(|quota| {
+                format!(
+                    "<li>{}: {} of {} emails</li>",
+                    quota.name, quota.used, quota.limit
+                )
+            })
+            .collect();
+        format!("<ul>{items}</ul>")
+    };
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
@@ -31,12 +48,19 @@ pub async fn admin_dashboard(
     <p>Available actions:</p>
     <ol>
         <li><a href="/admin/password">Change password</a></li>
+        <li><a href="/admin/subscribers">Subscribers</a></li>
+        <li><a href="/admin/lists">Mailing lists</a></li>
+        <li><a href="/admin/segments">Segments</a></li>
+        <li><a href="/admin/dead_letters">Failed deliveries</a></li>
+        <li><a href="/admin/api_tokens">API tokens</a></li>
         <li>
             <form name="logoutForm" action="/admin/logout" method="post">
                 <input type="submit" value="Logout">
             </form>
         </li>
     </ol>
+    <p>Sending quotas:</p>
+    {quota_usage}
 </body>
 </html>"#
         )))
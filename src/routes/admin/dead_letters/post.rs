@@ -0,0 +1,83 @@
+use crate::issue_delivery_worker::notify_new_tasks;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tracing::instrument(name = "requeue a dead letter", skip(form, pool))]
+pub async fn requeue_dead_letter(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued = requeue(&pool, form.newsletter_issue_id, &form.subscriber_email)
+        .await
+        .context("failed to requeue the delivery task")
+        .map_err(e500)?;
+    if requeued {
+        FlashMessage::info("the delivery has been requeued").send();
+    } else {
+        FlashMessage::error("the delivery could not be found").send();
+    }
+    Ok(see_other("/admin/dead_letters"))
+}
+
+/// Move a dead letter back into the delivery queue,
+/// with a fresh retry budget.
+async fn requeue(pool: &PgPool, issue_id: Uuid, email: &str) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_dead_letters
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if n_deleted_rows == 0 {
+        return Ok(false);
+    }
+    // The delivery is pending again: its failure no longer belongs
+    // in the issue report.
+    sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issue_deliveries
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        issue_id,
+        email,
+    )
+    .execute(&mut transaction)
+    .await?;
+    notify_new_tasks(&mut transaction).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,209 @@
+use crate::routes::DEFAULT_LIST;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub(crate) struct List {
+    pub(crate) name: String,
+    pub(crate) slug: String,
+}
+
+struct ListSummary {
+    name: String,
+    slug: String,
+    n_confirmed: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListFormData {
+    name: String,
+    slug: String,
+}
+
+pub async fn lists(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut lists_html = String::new();
+    for list in get_list_summaries(&pool).await.map_err(e500)? {
+        writeln!(
+            lists_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&list.name),
+            encode_minimal(&list.slug),
+            list.n_confirmed,
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Mailing lists</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Slug</th><th>Confirmed subscribers</th></tr>
+        {lists_html}
+    </table>
+    <h2>New list</h2>
+    <form action="/admin/lists" method="post">
+        <p><label>Name
+            <input type="text" name="name">
+        </label></p>
+        <p><label>Slug (lowercase letters, digits and dashes), used in subscription forms
+            <input type="text" name="slug">
+        </label></p>
+        <button type="submit">Create list</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "create a list", skip(form, pool))]
+pub async fn create_list(
+    form: web::Form<ListFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("the list must have a name").send();
+        return Ok(see_other("/admin/lists"));
+    }
+    if let Err(e) = validate_slug(&form.slug) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, name, slug)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        form.slug,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to create the list")
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted_rows == 0 {
+        FlashMessage::error("a list with the same name or slug already exists").send();
+    } else {
+        FlashMessage::info("the list has been created").send();
+    }
+    Ok(see_other("/admin/lists"))
+}
+
+fn validate_slug(slug: &str) -> Result<(), String> {
+    let is_valid = !slug.is_empty()
+        && slug.len() <= 50
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!("{} is not a valid slug", encode_minimal(slug)))
+    }
+}
+
+/// Checkboxes to pick the lists an issue is sent to, with the
+/// default list picked upfront.
+pub(crate) fn list_checkboxes(lists: &[List]) -> String {
+    let mut html = String::new();
+    for list in lists {
+        writeln!(
+            html,
+            r#"<label><input type="checkbox" name="list" value="{}"{}> {}</label><br>"#,
+            encode_attribute(&list.slug),
+            if list.slug == DEFAULT_LIST {
+                " checked"
+            } else {
+                ""
+            },
+            encode_minimal(&list.name),
+        )
+        .unwrap();
+    }
+    html
+}
+
+#[tracing::instrument(name = "get lists", skip(pool))]
+pub(crate) async fn get_lists(pool: &PgPool) -> Result<Vec<List>, anyhow::Error> {
+    let lists = sqlx::query_as!(List, r#"SELECT name, slug FROM lists ORDER BY name"#)
+        .fetch_all(pool)
+        .await
+        .context("failed to retrieve the lists")?;
+    Ok(lists)
+}
+
+#[tracing::instrument(name = "get list summaries", skip(pool))]
+async fn get_list_summaries(pool: &PgPool) -> Result<Vec<ListSummary>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.name,
+            l.slug,
+            COUNT(s.id) AS "n_confirmed!"
+        FROM lists l
+        LEFT JOIN list_subscriptions ls
+            ON ls.list_id = l.list_id AND ls.status = 'confirmed'
+        LEFT JOIN subscriptions s
+            ON s.id = ls.subscriber_id AND s.status = 'confirmed'
+        GROUP BY l.list_id
+        ORDER BY l.name
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the lists")?;
+    Ok(lists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_slug;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn lowercase_letters_digits_and_dashes_are_accepted() {
+        assert_ok!(validate_slug("rust-weekly-2"));
+    }
+
+    #[test]
+    fn empty_slugs_are_rejected() {
+        assert_err!(validate_slug(""));
+    }
+
+    #[test]
+    fn uppercase_letters_spaces_and_symbols_are_rejected() {
+        for slug in ["Rust", "rust weekly", "rust/weekly", "rüst"] {
+            assert_err!(validate_slug(slug));
+        }
+    }
+
+    #[test]
+    fn slugs_longer_than_50_characters_are_rejected() {
+        assert_err!(validate_slug(&"a".repeat(51)));
+    }
+}
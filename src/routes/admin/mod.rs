@@ -1,9 +1,19 @@
+mod api_tokens;
 mod dashboard;
+mod dead_letters;
+mod lists;
 mod logout;
 mod newsletter;
 mod password;
+mod segments;
+mod subscribers;
 
+pub use api_tokens::{api_tokens, create_api_token, revoke_api_token};
 pub use dashboard::admin_dashboard;
+pub use dead_letters::*;
+pub use lists::{create_list, lists};
 pub use logout::log_out;
 pub use newsletter::*;
 pub use password::*;
+pub use segments::{create_segment, segments};
+pub use subscribers::*;
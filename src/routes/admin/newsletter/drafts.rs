@@ -0,0 +1,483 @@
+use crate::authentication::UserId;
+use crate::configuration::IdempotencySettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_template::{IssueTemplate, TemplateContext};
+use crate::routes::admin::lists::{get_lists, list_checkboxes};
+use crate::routes::admin::newsletter::post::{
+    create_newsletter_issue, parse_segment_id, success_message, CreateIssueError, IssueContent,
+    NewIssue, NO_LIST_ERROR,
+};
+use crate::routes::admin::newsletter::scheduled::parse_optional_scheduled_for;
+use crate::routes::admin::segments::{get_segment_summaries, segment_select};
+use crate::utils::{e400, e404, e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use actix_web_lab::extract::UrlEncodedForm;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct Draft {
+    newsletter_issue_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    markdown_content: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    #[serde(default)]
+    text_content: String,
+    #[serde(default)]
+    html_content: String,
+    /// Replaces the two contents above when it is not empty
+    markdown_content: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestEmailFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishDraftFormData {
+    idempotency_key: String,
+    scheduled_for: Option<String>,
+    /// Slugs of the lists the issue is sent to
+    #[serde(default)]
+    list: Vec<String>,
+    /// Id of the segment the issue is restricted to, empty for none
+    segment: Option<String>,
+    /// Set to leave the issue out of the public archive
+    private: Option<String>,
+}
+
+pub async fn drafts(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut drafts_html = String::new();
+    for draft in get_drafts(&pool).await.map_err(e500)? {
+        writeln!(
+            drafts_html,
+            r#"<li><a href="/admin/newsletters/drafts/{}">{}</a></li>"#,
+            draft.newsletter_issue_id,
+            encode_minimal(&draft.title),
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Drafts</title>
+</head>
+<body>
+    {msg_html}
+    <h2>Drafts</h2>
+    <ul>
+        {drafts_html}
+    </ul>
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "save a draft", skip_all)]
+pub async fn create_draft(
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let DraftFormData {
+        title,
+        text_content,
+        html_content,
+        markdown_content,
+    } = form.into_inner();
+    let content = IssueContent::new(markdown_content, html_content, text_content);
+    let draft_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content,
+            status
+        )
+        VALUES ($1, $2, $3, $4, $5, 'draft')
+        "#,
+        draft_id,
+        title,
+        content.text_content,
+        content.html_content,
+        content.markdown_content,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to store the draft")
+    .map_err(e500)?;
+    FlashMessage::info("the draft has been saved").send();
+    Ok(see_other(&format!("/admin/newsletters/drafts/{draft_id}")))
+}
+
+pub async fn edit_draft_form(
+    draft_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = get_draft(&pool, draft_id.into_inner())
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("the draft does not exist"))?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let id = draft.newsletter_issue_id;
+    let title = encode_minimal(&draft.title);
+    let text_content = encode_minimal(&draft.text_content);
+    let html_content = encode_minimal(&draft.html_content);
+    let markdown_content = encode_minimal(draft.markdown_content.as_deref().unwrap_or_default());
+    let lists_html = list_checkboxes(&get_lists(&pool).await.map_err(e500)?);
+    let segment_html = segment_select(&get_segment_summaries(&pool).await.map_err(e500)?);
+    let idempotency_key = Uuid::new_v4();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit draft</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/newsletters/drafts/{id}" method="post">
+        <label>Title:<br>
+            <input type="text" name="title" value="{title}">
+        </label><br>
+        <p>The content is a template: <code>{{{{ subscriber.name }}}}</code>,
+            <code>{{{{ subscriber.email }}}}</code>, <code>{{{{ issue.title }}}}</code>,
+            <code>{{{{ unsubscribe_url }}}}</code> and <code>{{{{ preferences_url }}}}</code>
+            are replaced for every subscriber.</p>
+        <label>Markdown content (replaces the plain text and HTML contents below):<br>
+            <textarea name="markdown_content" rows="20" cols="50">{markdown_content}</textarea>
+        </label><br>
+        <label>Plain text content:<br>
+            <textarea name="text_content" rows="20" cols="50">{text_content}</textarea>
+        </label><br>
+        <label>HTML content:<br>
+            <textarea name="html_content" rows="20" cols="50">{html_content}</textarea>
+        </label><br>
+        <button type="submit">Save draft</button>
+    </form>
+    <p><a href="/admin/newsletters/drafts/{id}/preview" target="_blank">Preview</a></p>
+    <form action="/admin/newsletters/drafts/{id}/test" method="post">
+        <label>Send a test email to:
+            <input type="email" placeholder="Enter an email address" name="email">
+        </label>
+        <button type="submit">Send test email</button>
+    </form>
+    <form action="/admin/newsletters/drafts/{id}/publish" method="post">
+        <label>Schedule for (UTC, leave empty to publish now):
+            <input type="datetime-local" name="scheduled_for">
+        </label>
+        <fieldset><legend>Send to</legend>
+            {lists_html}
+        </fieldset>
+        <label>Only to the confirmed subscribers in
+            {segment_html}
+        </label>
+        <label><input type="checkbox" name="private"> Keep out of the public archive</label>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Publish</button>
+    </form>
+    <p><a href="/admin/newsletters/drafts">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "update a draft", skip(form, pool))]
+pub async fn update_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let DraftFormData {
+        title,
+        text_content,
+        html_content,
+        markdown_content,
+    } = form.into_inner();
+    let content = IssueContent::new(markdown_content, html_content, text_content);
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, markdown_content = $5
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+        title,
+        content.text_content,
+        content.html_content,
+        content.markdown_content,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to update the draft")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(e404("the draft does not exist"));
+    }
+    FlashMessage::info("the draft has been saved").send();
+    Ok(see_other(&format!("/admin/newsletters/drafts/{draft_id}")))
+}
+
+pub async fn preview_draft(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = get_draft(&pool, draft_id.into_inner())
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("the draft does not exist"))?;
+    let rendered = IssueTemplate::parse(&draft.html_content, &draft.text_content)
+        .and_then(|template| template.render(&TemplateContext::sample(&draft.title)))
+        .map_err(|e| e400(format!("the issue content is not a valid template: {e}")))?;
+    // The HTML content is rendered as-is, with sample values:
+    // this is what subscribers will see.
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {html_content}
+    <hr>
+    <pre>{text_content}</pre>
+</body>
+</html>"#,
+            title = encode_minimal(&draft.title),
+            html_content = rendered.html_content,
+            text_content = encode_minimal(&rendered.text_content),
+        )))
+}
+
+#[tracing::instrument(name = "send a test email", skip(form, pool, email_client))]
+pub async fn send_test_email(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<TestEmailFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = get_draft(&pool, draft_id.into_inner())
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("the draft does not exist"))?;
+    let edit_url = format!("/admin/newsletters/drafts/{}", draft.newsletter_issue_id);
+    let recipient = match SubscriberEmail::parse(form.0.email) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&edit_url));
+        }
+    };
+    let mut context = TemplateContext::sample(&draft.title);
+    context.subscriber.email = recipient.as_ref();
+    let rendered = match IssueTemplate::parse(&draft.html_content, &draft.text_content)
+        .and_then(|template| template.render(&context))
+    {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&format!(
+                "the issue content is not a valid template: {e}"
+            )))
+            .send();
+            return Ok(see_other(&edit_url));
+        }
+    };
+    email_client
+        .send_email(
+            &recipient,
+            &format!("[Test] {}", draft.title),
+            &rendered.html_content,
+            &rendered.text_content,
+        )
+        .await
+        .context("failed to send the test email")
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "a test email has been sent to {}",
+        encode_minimal(recipient.as_ref())
+    ))
+    .send();
+    Ok(see_other(&edit_url))
+}
+
+#[tracing::instrument(
+    name = "publish a draft",
+    skip(form, pool, idempotency_settings, user_id),
+    fields(user_id=%&*user_id)
+)]
+pub async fn publish_draft(
+    draft_id: web::Path<Uuid>,
+    form: UrlEncodedForm<PublishDraftFormData>,
+    pool: web::Data<PgPool>,
+    idempotency_settings: web::Data<IdempotencySettings>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let draft_id = draft_id.into_inner();
+    let PublishDraftFormData {
+        idempotency_key,
+        scheduled_for,
+        list: lists,
+        segment,
+        private,
+    } = form.into_inner();
+    let scheduled_for = match parse_optional_scheduled_for(scheduled_for.as_deref()) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&format!("/admin/newsletters/drafts/{draft_id}")));
+        }
+    };
+    if lists.is_empty() {
+        FlashMessage::error(NO_LIST_ERROR).send();
+        return Ok(see_other(&format!("/admin/newsletters/drafts/{draft_id}")));
+    }
+    let segment_id = parse_segment_id(segment.as_deref()).map_err(e400)?;
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let mut transaction = match try_processing(
+        &pool,
+        &idempotency_key,
+        *user_id,
+        idempotency_settings.retention(),
+    )
+    .await
+    .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message(scheduled_for).send();
+            return Ok(saved_response);
+        }
+    };
+    // The draft is validated as it is published: it cannot be edited in
+    // the meantime. Dropping the transaction on error releases the
+    // idempotency key.
+    let draft = lock_draft(&mut transaction, draft_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("the draft does not exist"))?;
+    // A broken template must be caught before any delivery is queued
+    IssueTemplate::validate(&draft.title, &draft.html_content, &draft.text_content)
+        .map_err(e400)?;
+    let issue = NewIssue {
+        draft_id: Some(draft_id),
+        title: draft.title,
+        content: IssueContent {
+            markdown_content: draft.markdown_content,
+            html_content: draft.html_content,
+            text_content: draft.text_content,
+        },
+        scheduled_for,
+        lists,
+        segment_id,
+        is_private: private.is_some(),
+    };
+    create_newsletter_issue(&mut transaction, &issue)
+        .await
+        .map_err(|e| match e {
+            CreateIssueError::UnknownList | CreateIssueError::UnknownSegment => e400(e),
+            CreateIssueError::UnexpectedError(_) => e500(e),
+        })?;
+
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message(scheduled_for).send();
+    Ok(response)
+}
+
+#[tracing::instrument(name = "get draft", skip(pool))]
+async fn get_draft(pool: &PgPool, draft_id: Uuid) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT newsletter_issue_id, title, text_content, html_content, markdown_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve the draft")?;
+    Ok(draft)
+}
+
+/// Like [`get_draft`], keeping the draft locked until the transaction ends.
+#[tracing::instrument(name = "lock draft", skip(transaction))]
+async fn lock_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    draft_id: Uuid,
+) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT newsletter_issue_id, title, text_content, html_content, markdown_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        FOR UPDATE
+        "#,
+        draft_id,
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("failed to retrieve the draft")?;
+    Ok(draft)
+}
+
+#[tracing::instrument(name = "get drafts", skip(pool))]
+async fn get_drafts(pool: &PgPool) -> Result<Vec<Draft>, anyhow::Error> {
+    let drafts = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT newsletter_issue_id, title, text_content, html_content, markdown_content
+        FROM newsletter_issues
+        WHERE status = 'draft'
+        ORDER BY title
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the drafts")?;
+    Ok(drafts)
+}
@@ -1,15 +1,47 @@
+use crate::routes::admin::lists::{get_lists, list_checkboxes};
+use crate::routes::admin::segments::{get_segment_summaries, segment_select};
+use crate::utils::e500;
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
 use std::fmt::Write;
+use uuid::Uuid;
+
+struct PublishedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: Option<DateTime<Utc>>,
+}
 
 pub async fn publish_newsletter_form(
     flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
+    let mut issues_html = String::new();
+    for issue in get_published_issues(&pool).await.map_err(e500)? {
+        let published_at = issue
+            .published_at
+            .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_default();
+        writeln!(
+            issues_html,
+            r#"<li><a href="/admin/newsletters/{}">{}</a> ({})</li>"#,
+            issue.newsletter_issue_id,
+            encode_minimal(&issue.title),
+            published_at,
+        )
+        .unwrap();
+    }
+    let lists_html = list_checkboxes(&get_lists(&pool).await.map_err(e500)?);
+    let segment_html = segment_select(&get_segment_summaries(&pool).await.map_err(e500)?);
     let idempotency_key = uuid::Uuid::new_v4();
     Ok(HttpResponse::Ok()
        .content_type(ContentType::html())
@@ -26,17 +58,58 @@ pub async fn publish_newsletter_form(
         <label>Title:<br>
             <input type="text" placeholder="Enter the issue title" name="title">
         </label><br>
+        <p>The content is a template: <code>{{{{ subscriber.name }}}}</code>,
+            <code>{{{{ subscriber.email }}}}</code>, <code>{{{{ issue.title }}}}</code>,
+            <code>{{{{ unsubscribe_url }}}}</code> and <code>{{{{ preferences_url }}}}</code>
+            are replaced for every subscriber.</p>
+        <label>Markdown content (replaces the plain text and HTML contents below):<br>
+            <textarea placeholder="Or write the content in Markdown" name="markdown_content" rows="20" cols="50"></textarea>
+        </label><br>
         <label>Plain text content:<br>
             <textarea placeholder="Enter the content in plain text" name="text_content" rows="20" cols="50"></textarea>
         </label><br>
         <label>HTML content:<br>
             <textarea placeholder="Enter the content in HTML format" name="html_content" rows="20" cols="50"></textarea>
         </label><br>
+        <label>Schedule for (UTC, leave empty to publish now):<br>
+            <input type="datetime-local" name="scheduled_for">
+        </label><br>
+        <fieldset><legend>Send to</legend>
+            {lists_html}
+        </fieldset>
+        <label>Only to the confirmed subscribers in
+            {segment_html}
+        </label><br>
+        <label><input type="checkbox" name="private"> Keep out of the public archive</label><br>
         <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
         <button type="submit">Publish</button>
+        <button type="submit" formaction="/admin/newsletters/drafts">Save as draft</button>
     </form>
+    <p><a href="/admin/newsletters/drafts">Drafts</a></p>
+    <p><a href="/admin/newsletters/scheduled">Scheduled issues</a></p>
+    <h2>Published issues</h2>
+    <ul>
+        {issues_html}
+    </ul>
     <p><a href="/admin/dashboard">&lt; - Back</a></p>
 </body>
 </html>"#,
     )))
 }
+
+#[tracing::instrument(name = "get published issues", skip(pool))]
+async fn get_published_issues(pool: &PgPool) -> Result<Vec<PublishedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT newsletter_issue_id, title, published_at
+        FROM newsletter_issues
+        WHERE status = 'published'
+        ORDER BY published_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the published newsletter issues")?;
+    Ok(issues)
+}
@@ -1,5 +1,18 @@
+mod drafts;
 mod get;
 mod post;
+mod report;
+mod scheduled;
 
+pub use drafts::{
+    create_draft, drafts, edit_draft_form, preview_draft, publish_draft, send_test_email,
+    update_draft,
+};
 pub use get::publish_newsletter_form;
 pub use post::publish_newsletter;
+pub(crate) use post::{create_newsletter_issue, CreateIssueError, IssueContent, NewIssue};
+pub(crate) use report::{
+    get_delivery_counts, get_engagement_counts, DeliveryCounts, EngagementCounts,
+};
+pub use report::{newsletter_issue_report, set_issue_visibility};
+pub use scheduled::{cancel_scheduled_issue, reschedule_issue, scheduled_issues};
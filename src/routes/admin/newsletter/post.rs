@@ -1,25 +1,86 @@
 use crate::authentication::UserId;
+use crate::configuration::IdempotencySettings;
 use crate::idempotency::save_response;
 use crate::idempotency::IdempotencyKey;
 use crate::idempotency::{try_processing, NextAction};
+use crate::issue_delivery_worker::{enqueue_delivery_tasks, get_issue_segment};
+use crate::issue_template::IssueTemplate;
+use crate::markdown::MarkdownContent;
+use crate::routes::admin::newsletter::scheduled::parse_optional_scheduled_for;
+use crate::routes::error_chain_fmt;
 use crate::utils::{e400, e500, see_other};
 use actix_web::web::ReqData;
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::extract::UrlEncodedForm;
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     title: String,
+    #[serde(default)]
     text_content: String,
+    #[serde(default)]
     html_content: String,
+    /// Replaces the two contents above when it is not empty
+    markdown_content: Option<String>,
     idempotency_key: String,
+    // Left empty to publish the issue right away
+    scheduled_for: Option<String>,
+    /// Slugs of the lists the issue is sent to
+    #[serde(default)]
+    list: Vec<String>,
+    /// Id of the segment the issue is restricted to, empty for none
+    segment: Option<String>,
+    /// Set to leave the issue out of the public archive
+    private: Option<String>,
 }
 
-fn success_message() -> FlashMessage {
-    FlashMessage::info("the newsletter issue has been accepted")
+/// The contents of an issue, rendered from its Markdown source if it has one.
+pub(crate) struct IssueContent {
+    pub(crate) markdown_content: Option<String>,
+    pub(crate) html_content: String,
+    pub(crate) text_content: String,
+}
+
+impl IssueContent {
+    pub(crate) fn new(
+        markdown_content: Option<String>,
+        html_content: String,
+        text_content: String,
+    ) -> Self {
+        match markdown_content.filter(|m| !m.trim().is_empty()) {
+            Some(markdown_content) => {
+                let rendered = MarkdownContent::render(&markdown_content);
+                Self {
+                    markdown_content: Some(markdown_content),
+                    html_content: rendered.html_content,
+                    text_content: rendered.text_content,
+                }
+            }
+            None => Self {
+                markdown_content: None,
+                html_content,
+                text_content,
+            },
+        }
+    }
+}
+
+pub(super) const NO_LIST_ERROR: &str = "pick at least one list to send the issue to";
+
+pub(super) fn success_message(scheduled_for: Option<DateTime<Utc>>) -> FlashMessage {
+    match scheduled_for {
+        Some(scheduled_for) => FlashMessage::info(format!(
+            "the newsletter issue has been scheduled for {}",
+            scheduled_for.format("%Y-%m-%d %H:%M UTC")
+        )),
+        None => FlashMessage::info("the newsletter issue has been accepted"),
+    }
 }
 
 #[tracing::instrument(
@@ -28,8 +89,9 @@ fn success_message() -> FlashMessage {
     fields(user_id=%&*user_id)
 )]
 pub async fn publish_newsletter(
-    form: web::Form<FormData>,
+    form: UrlEncodedForm<FormData>,
     pool: web::Data<PgPool>,
+    idempotency_settings: web::Data<IdempotencySettings>,
     user_id: ReqData<UserId>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
@@ -37,34 +99,66 @@ pub async fn publish_newsletter(
         title,
         text_content,
         html_content,
+        markdown_content,
         idempotency_key,
-    } = form.0;
+        scheduled_for,
+        list: lists,
+        segment,
+        private,
+    } = form.into_inner();
+    let content = IssueContent::new(markdown_content, html_content, text_content);
+    let scheduled_for = match parse_optional_scheduled_for(scheduled_for.as_deref()) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+    if lists.is_empty() {
+        FlashMessage::error(NO_LIST_ERROR).send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+    let segment_id = parse_segment_id(segment.as_deref()).map_err(e400)?;
+    // A broken template must be caught before any delivery is queued
+    IssueTemplate::validate(&title, &content.html_content, &content.text_content).map_err(e400)?;
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
-    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
-        .await
-        .map_err(e500)?
+    let mut transaction = match try_processing(
+        &pool,
+        &idempotency_key,
+        *user_id,
+        idempotency_settings.retention(),
+    )
+    .await
+    .map_err(e500)?
     {
         NextAction::StartProcessing(t) => t,
         NextAction::ReturnSavedResponse(saved_response) => {
-            success_message().send();
+            success_message(scheduled_for).send();
             return Ok(saved_response);
         }
     };
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("failed to store newsletter issue details")
-        .map_err(e500)?;
-
-    enqueue_delivery_tasks(&mut transaction, issue_id)
+    let issue = NewIssue {
+        draft_id: None,
+        title,
+        content,
+        scheduled_for,
+        lists,
+        segment_id,
+        is_private: private.is_some(),
+    };
+    // Dropping the transaction on error releases the idempotency key.
+    create_newsletter_issue(&mut transaction, &issue)
         .await
-        .context("failed to enqueue delivery tasks")
-        .map_err(e500)?;
+        .map_err(|e| match e {
+            CreateIssueError::UnknownList | CreateIssueError::UnknownSegment => e400(e),
+            CreateIssueError::UnexpectedError(_) => e500(e),
+        })?;
 
     let response = see_other("/admin/newsletters");
     let response = save_response(transaction, &idempotency_key, *user_id, response)
         .await
         .map_err(e500)?;
-    success_message().send();
+    success_message(scheduled_for).send();
     Ok(response)
 }
 
@@ -90,12 +184,94 @@ pub async fn publish_newsletter(
 //     Ok(confirmed_subscribers)
 // }
 
+/// A newsletter issue about to be published, or scheduled.
+pub(crate) struct NewIssue {
+    /// The draft turned into the issue, if any: it keeps its id
+    pub(crate) draft_id: Option<Uuid>,
+    pub(crate) title: String,
+    pub(crate) content: IssueContent,
+    pub(crate) scheduled_for: Option<DateTime<Utc>>,
+    /// Slugs of the lists the issue is sent to
+    pub(crate) lists: Vec<String>,
+    pub(crate) segment_id: Option<Uuid>,
+    pub(crate) is_private: bool,
+}
+
+#[derive(thiserror::Error)]
+pub(crate) enum CreateIssueError {
+    #[error("the issue is sent to a list that does not exist")]
+    UnknownList,
+    #[error("the segment does not exist")]
+    UnknownSegment,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for CreateIssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Store a new issue and, unless it is scheduled, queue its deliveries.
+///
+/// Nothing is sent until the transaction is committed. A draft must have
+/// been locked by the caller, in the same transaction.
+#[tracing::instrument(skip_all, fields(title = %issue.title))]
+pub(crate) async fn create_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue: &NewIssue,
+) -> Result<Uuid, CreateIssueError> {
+    let issue_id = match issue.draft_id {
+        Some(draft_id) => {
+            if !publish_draft_as_issue(transaction, draft_id, issue)
+                .await
+                .context("failed to publish the draft")?
+            {
+                return Err(anyhow::anyhow!("the draft {draft_id} does not exist").into());
+            }
+            draft_id
+        }
+        None => insert_newsletter_issue(
+            transaction,
+            &issue.title,
+            &issue.content,
+            issue.scheduled_for,
+            issue.is_private,
+        )
+        .await
+        .context("failed to store newsletter issue details")?,
+    };
+    if !insert_issue_lists(transaction, issue_id, &issue.lists)
+        .await
+        .context("failed to store the lists of the newsletter issue")?
+    {
+        return Err(CreateIssueError::UnknownList);
+    }
+    if let Some(segment_id) = issue.segment_id {
+        if !set_issue_segment(transaction, issue_id, segment_id)
+            .await
+            .context("failed to store the segment of the newsletter issue")?
+        {
+            return Err(CreateIssueError::UnknownSegment);
+        }
+    }
+    // Scheduled issues are enqueued by the scheduler once they are due
+    if issue.scheduled_for.is_none() {
+        enqueue_issue(transaction, issue_id)
+            .await
+            .context("failed to enqueue delivery tasks")?;
+    }
+    Ok(issue_id)
+}
+
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
     title: &str,
-    text_content: &str,
-    html_content: &str,
+    content: &IssueContent,
+    scheduled_for: Option<DateTime<Utc>>,
+    is_private: bool,
 ) -> Result<uuid::Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -105,38 +281,130 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
-            published_at
+            markdown_content,
+            published_at,
+            scheduled_for,
+            status,
+            is_private
+        )
+        VALUES (
+            $1, $2, $3, $4, $5,
+            CASE WHEN $6::timestamptz IS NULL THEN now() END,
+            $6,
+            CASE WHEN $6::timestamptz IS NULL THEN 'published' ELSE 'scheduled' END,
+            $7
         )
-        VALUES ($1, $2, $3, $4, now())
         "#,
         newsletter_issue_id,
         title,
-        text_content,
-        html_content,
+        content.text_content,
+        content.html_content,
+        content.markdown_content,
+        scheduled_for,
+        is_private,
     )
     .execute(transaction)
     .await?;
     Ok(newsletter_issue_id)
 }
 
-#[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+/// Returns `false` if the draft does not exist.
+#[tracing::instrument(skip(transaction, issue))]
+async fn publish_draft_as_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    draft_id: Uuid,
+    issue: &NewIssue,
+) -> Result<bool, sqlx::Error> {
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            markdown_content = $5,
+            published_at = CASE WHEN $6::timestamptz IS NULL THEN now() END,
+            scheduled_for = $6,
+            status = CASE WHEN $6::timestamptz IS NULL THEN 'published' ELSE 'scheduled' END,
+            is_private = $7
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+        issue.title,
+        issue.content.text_content,
+        issue.content.html_content,
+        issue.content.markdown_content,
+        issue.scheduled_for,
+        issue.is_private,
+    )
+    .execute(transaction)
+    .await?
+    .rows_affected();
+    Ok(n_updated_rows > 0)
+}
+
+/// Returns `false` if some of the lists do not exist.
+#[tracing::instrument(skip(transaction))]
+async fn insert_issue_lists(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+    list_slugs: &[String],
+) -> Result<bool, sqlx::Error> {
+    let n_lists = list_slugs.iter().collect::<HashSet<_>>().len();
+    let n_inserted_rows = sqlx::query!(
         r#"
-        INSERT INTO issue_delivery_queue ( 
-            newsletter_issue_id,
-            subscriber_email
-        )
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id FROM lists WHERE slug = ANY($2)
         "#,
         newsletter_issue_id,
+        list_slugs,
     )
     .execute(transaction)
-    .await?;
+    .await?
+    .rows_affected();
+    Ok(n_inserted_rows == n_lists as u64)
+}
+
+/// An empty value means that the issue goes to everybody on its lists.
+pub(super) fn parse_segment_id(segment: Option<&str>) -> Result<Option<Uuid>, String> {
+    match segment.filter(|s| !s.is_empty()) {
+        Some(segment) => segment
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{segment} is not a valid segment id")),
+        None => Ok(None),
+    }
+}
+
+/// Returns `false` if the segment does not exist.
+#[tracing::instrument(skip(transaction))]
+async fn set_issue_segment(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    segment_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET segment_id = sg.segment_id
+        FROM segments sg
+        WHERE i.newsletter_issue_id = $1 AND sg.segment_id = $2
+        "#,
+        newsletter_issue_id,
+        segment_id,
+    )
+    .execute(transaction)
+    .await?
+    .rows_affected();
+    Ok(n_updated_rows > 0)
+}
+
+/// Queue the deliveries of an issue, to its segment if it has one.
+async fn enqueue_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let segment = get_issue_segment(transaction, newsletter_issue_id).await?;
+    enqueue_delivery_tasks(transaction, newsletter_issue_id, segment.as_ref()).await?;
     Ok(())
 }
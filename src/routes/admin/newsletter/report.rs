@@ -0,0 +1,272 @@
+use crate::utils::{e400, e404, e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub(crate) struct DeliveryCounts {
+    pub(crate) sent: i64,
+    pub(crate) failed: i64,
+    pub(crate) skipped: i64,
+    pub(crate) pending: i64,
+    pub(crate) retrying: i64,
+}
+
+/// Number of subscribers who opened the issue, or clicked one of its links.
+#[derive(serde::Serialize)]
+pub(crate) struct EngagementCounts {
+    pub(crate) opens: i64,
+    pub(crate) clicks: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct VisibilityFormData {
+    /// `private` to leave the issue out of the public archive
+    visibility: String,
+}
+
+struct FailedDelivery {
+    subscriber_email: String,
+    status: String,
+    error: Option<String>,
+    recorded_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "newsletter issue delivery report", skip(pool))]
+pub async fn newsletter_issue_report(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let (title, is_private) = get_issue_title(&pool, issue_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("the newsletter issue does not exist"))?;
+    let counts = get_delivery_counts(&pool, issue_id).await.map_err(e500)?;
+    let engagement = get_engagement_counts(&pool, issue_id).await.map_err(e500)?;
+    let failures = get_failed_deliveries(&pool, issue_id).await.map_err(e500)?;
+
+    let mut failures_html = String::new();
+    for f in failures {
+        writeln!(
+            failures_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&f.subscriber_email),
+            f.status,
+            f.recorded_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            encode_minimal(f.error.as_deref().unwrap_or_default()),
+        )
+        .unwrap();
+    }
+
+    let visibility_html = if is_private {
+        format!(
+            r#"<p>This issue is private: it is not in the public archive.</p>
+    <form action="/admin/newsletters/{issue_id}/visibility" method="post">
+        <input hidden type="text" name="visibility" value="public">
+        <button type="submit">Add to the public archive</button>
+    </form>"#
+        )
+    } else {
+        format!(
+            r#"<p>This issue is in the <a href="/archive/{issue_id}">public archive</a>.</p>
+    <form action="/admin/newsletters/{issue_id}/visibility" method="post">
+        <input hidden type="text" name="visibility" value="private">
+        <button type="submit">Keep private</button>
+    </form>"#
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Delivery report</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    {visibility_html}
+    <ul>
+        <li>Sent: {sent}</li>
+        <li>Failed: {failed}</li>
+        <li>Skipped: {skipped}</li>
+        <li>Pending: {pending} ({retrying} retrying)</li>
+    </ul>
+    <ul>
+        <li>Open rate: {open_rate} ({opens} opened)</li>
+        <li>Click rate: {click_rate} ({clicks} clicked)</li>
+    </ul>
+    <table>
+        <tr>
+            <th>Subscriber</th>
+            <th>Status</th>
+            <th>Recorded at</th>
+            <th>Error</th>
+        </tr>
+        {failures_html}
+    </table>
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = encode_minimal(&title),
+            sent = counts.sent,
+            failed = counts.failed,
+            skipped = counts.skipped,
+            pending = counts.pending,
+            retrying = counts.retrying,
+            open_rate = rate(engagement.opens, counts.sent),
+            opens = engagement.opens,
+            click_rate = rate(engagement.clicks, counts.sent),
+            clicks = engagement.clicks,
+        )))
+}
+
+#[tracing::instrument(name = "set the visibility of a newsletter issue", skip(form, pool))]
+pub async fn set_issue_visibility(
+    issue_id: web::Path<Uuid>,
+    form: web::Form<VisibilityFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let is_private = match form.visibility.as_str() {
+        "private" => true,
+        "public" => false,
+        other => return Err(e400(format!("{other} is not a valid visibility"))),
+    };
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET is_private = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+        is_private,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to update the visibility of the newsletter issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(e404("the newsletter issue does not exist"));
+    }
+    Ok(see_other(&format!("/admin/newsletters/{issue_id}")))
+}
+
+/// Share of the recipients, as a percentage.
+fn rate(count: i64, n_recipients: i64) -> String {
+    if n_recipients == 0 {
+        return "n/a".into();
+    }
+    format!("{:.1}%", 100.0 * count as f64 / n_recipients as f64)
+}
+
+/// Returns the title of the issue and whether it is private.
+#[tracing::instrument(skip(pool))]
+async fn get_issue_title(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<(String, bool)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT title, is_private
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve the newsletter issue")?;
+    Ok(row.map(|r| (r.title, r.is_private)))
+}
+
+#[tracing::instrument(skip(pool))]
+pub(crate) async fn get_delivery_counts(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<DeliveryCounts, anyhow::Error> {
+    let counts = sqlx::query_as!(
+        DeliveryCounts,
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*) FROM newsletter_issue_deliveries
+                WHERE newsletter_issue_id = $1 AND status = 'sent'
+            ) AS "sent!",
+            (
+                SELECT COUNT(*) FROM newsletter_issue_deliveries
+                WHERE newsletter_issue_id = $1 AND status = 'failed'
+            ) AS "failed!",
+            (
+                SELECT COUNT(*) FROM newsletter_issue_deliveries
+                WHERE newsletter_issue_id = $1 AND status = 'skipped'
+            ) AS "skipped!",
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue
+                WHERE newsletter_issue_id = $1
+            ) AS "pending!",
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue
+                WHERE newsletter_issue_id = $1 AND n_retries > 0
+            ) AS "retrying!"
+        "#,
+        issue_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count the deliveries of the newsletter issue")?;
+    Ok(counts)
+}
+
+#[tracing::instrument(skip(pool))]
+pub(crate) async fn get_engagement_counts(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<EngagementCounts, anyhow::Error> {
+    // Subscribers are counted once, however many times they opened or clicked
+    let counts = sqlx::query_as!(
+        EngagementCounts,
+        r#"
+        SELECT
+            COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'open') AS "opens!",
+            COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'click') AS "clicks!"
+        FROM newsletter_issue_events
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count the opens and clicks of the newsletter issue")?;
+    Ok(counts)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_failed_deliveries(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<FailedDelivery>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT subscriber_email, status, error, recorded_at
+        FROM newsletter_issue_deliveries
+        WHERE newsletter_issue_id = $1 AND status <> 'sent'
+        ORDER BY recorded_at DESC
+        "#,
+        issue_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the failed deliveries of the newsletter issue")?;
+    Ok(failures)
+}
@@ -0,0 +1,216 @@
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    scheduled_for: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleFormData {
+    scheduled_for: String,
+}
+
+/// Parse the value of a `datetime-local` input, interpreted as UTC.
+/// Only future dates are accepted.
+pub fn parse_scheduled_for(s: &str) -> Result<DateTime<Utc>, &'static str> {
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| "the scheduled date is not valid")?;
+    let scheduled_for = Utc.from_utc_datetime(&naive);
+    if scheduled_for <= Utc::now() {
+        return Err("the scheduled date must be in the future");
+    }
+    Ok(scheduled_for)
+}
+
+/// Parse the optional `scheduled_for` field of a publishing form:
+/// an empty value means "publish right away".
+pub fn parse_optional_scheduled_for(
+    s: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, &'static str> {
+    match s.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => parse_scheduled_for(s).map(Some),
+    }
+}
+
+pub async fn scheduled_issues(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut issues_html = String::new();
+    for issue in get_scheduled_issues(&pool).await.map_err(e500)? {
+        let scheduled_for = issue
+            .scheduled_for
+            .map(|t| t.format("%Y-%m-%dT%H:%M").to_string())
+            .unwrap_or_default();
+        writeln!(
+            issues_html,
+            r#"<tr>
+            <td>{title}</td>
+            <td>{scheduled_for} UTC</td>
+            <td>
+                <form action="/admin/newsletters/{id}/reschedule" method="post">
+                    <input type="datetime-local" name="scheduled_for" value="{scheduled_for}">
+                    <button type="submit">Reschedule</button>
+                </form>
+            </td>
+            <td>
+                <form action="/admin/newsletters/{id}/cancel" method="post">
+                    <button type="submit">Cancel</button>
+                </form>
+            </td>
+        </tr>"#,
+            id = issue.newsletter_issue_id,
+            title = encode_minimal(&issue.title),
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Scheduled issues</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr>
+            <th>Title</th>
+            <th>Scheduled for</th>
+            <th></th>
+            <th></th>
+        </tr>
+        {issues_html}
+    </table>
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "cancel a scheduled issue", skip(pool))]
+pub async fn cancel_scheduled_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id.into_inner(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to cancel the scheduled issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        FlashMessage::error("the issue is no longer scheduled").send();
+    } else {
+        FlashMessage::info("the scheduled issue has been cancelled").send();
+    }
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(name = "reschedule an issue", skip(form, pool))]
+pub async fn reschedule_issue(
+    issue_id: web::Path<Uuid>,
+    form: web::Form<RescheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let scheduled_for = match parse_scheduled_for(form.scheduled_for.trim()) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletters/scheduled"));
+        }
+    };
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id.into_inner(),
+        scheduled_for,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to reschedule the issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        FlashMessage::error("the issue is no longer scheduled").send();
+    } else {
+        FlashMessage::info("the issue has been rescheduled").send();
+    }
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(name = "get scheduled issues", skip(pool))]
+async fn get_scheduled_issues(pool: &PgPool) -> Result<Vec<ScheduledIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, scheduled_for
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY scheduled_for
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the scheduled newsletter issues")?;
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_scheduled_for;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn future_dates_are_accepted() {
+        let tomorrow = Utc::now() + Duration::days(1);
+        let input = tomorrow.format("%Y-%m-%dT%H:%M").to_string();
+        let parsed = parse_scheduled_for(&input).unwrap();
+        assert_eq!(parsed.format("%Y-%m-%dT%H:%M").to_string(), input);
+    }
+
+    #[test]
+    fn seconds_are_optional() {
+        let tomorrow = Utc::now() + Duration::days(1);
+        let input = tomorrow.format("%Y-%m-%dT%H:%M:%S").to_string();
+        assert!(parse_scheduled_for(&input).is_ok());
+    }
+
+    #[test]
+    fn past_dates_are_rejected() {
+        assert!(parse_scheduled_for("2020-01-01T10:00").is_err());
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(parse_scheduled_for("next tuesday").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::domain::SegmentFilter;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::Utc;
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub(crate) struct SegmentSummary {
+    pub(crate) segment_id: Uuid,
+    pub(crate) name: String,
+    pub(crate) filter: String,
+    /// How many confirmed subscribers match the filter right now
+    pub(crate) n_matching: i64,
+}
+
+/// Filled in by the "Preview" button of the form.
+#[derive(serde::Deserialize)]
+pub struct SegmentQuery {
+    name: Option<String>,
+    filter: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SegmentFormData {
+    name: String,
+    filter: String,
+}
+
+pub async fn segments(
+    query: web::Query<SegmentQuery>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let filter = query.filter.as_deref().unwrap_or_default();
+    let mut preview_html = String::new();
+    if !filter.trim().is_empty() {
+        match SegmentFilter::parse(filter) {
+            Ok(filter) => {
+                let n_matching = count_matching_subscribers(&pool, &filter)
+                    .await
+                    .map_err(e500)?;
+                write!(
+                    preview_html,
+                    "<p>{n_matching} confirmed subscriber(s) match this filter.</p>"
+                )
+                .unwrap();
+            }
+            Err(e) => write!(preview_html, "<p><i>{}</i></p>", encode_minimal(&e)).unwrap(),
+        }
+    }
+    let mut segments_html = String::new();
+    for segment in get_segment_summaries(&pool).await.map_err(e500)? {
+        writeln!(
+            segments_html,
+            "<tr><td>{}</td><td><code>{}</code></td><td>{}</td></tr>",
+            encode_minimal(&segment.name),
+            encode_minimal(&segment.filter),
+            segment.n_matching,
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Segments</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Filter</th><th>Matching confirmed subscribers</th></tr>
+        {segments_html}
+    </table>
+    <h2>New segment</h2>
+    <p>Combine conditions with <code>and</code>, <code>or</code>, <code>not</code>
+        and parentheses, e.g.
+        <code>tag = "beta" or (subscribed_days_ago &lt;= 30 and attributes.plan != "free")</code>.
+        Attributes can be compared with strings, numbers, <code>true</code> and <code>false</code>.</p>
+    <form action="/admin/segments" method="post">
+        <p><label>Name
+            <input type="text" name="name" value="{name}">
+        </label></p>
+        <p><label>Filter<br>
+            <textarea name="filter" rows="4" cols="80">{filter}</textarea>
+        </label></p>
+        {preview_html}
+        <button type="submit" formaction="/admin/segments" formmethod="get">Preview</button>
+        <button type="submit">Save segment</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            name = encode_attribute(query.name.as_deref().unwrap_or_default()),
+            filter = encode_minimal(filter),
+        )))
+}
+
+#[tracing::instrument(name = "create a segment", skip(form, pool))]
+pub async fn create_segment(
+    form: web::Form<SegmentFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("the segment must have a name").send();
+        return Ok(see_other("/admin/segments"));
+    }
+    if let Err(e) = SegmentFilter::parse(&form.filter) {
+        FlashMessage::error(encode_minimal(&e)).send();
+        return Ok(see_other("/admin/segments"));
+    }
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO segments (segment_id, name, filter, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        form.filter.trim(),
+        Utc::now(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to create the segment")
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted_rows == 0 {
+        FlashMessage::error("a segment with the same name already exists").send();
+    } else {
+        FlashMessage::info("the segment has been created").send();
+    }
+    Ok(see_other("/admin/segments"))
+}
+
+/// A select to restrict an issue to a segment, with how many
+/// subscribers each of them matches.
+pub(crate) fn segment_select(segments: &[SegmentSummary]) -> String {
+    let mut html = String::from(r#"<option value="">Everybody on the lists</option>"#);
+    for segment in segments {
+        write!(
+            html,
+            r#"<option value="{}">{} ({} matching)</option>"#,
+            segment.segment_id,
+            encode_minimal(&segment.name),
+            segment.n_matching,
+        )
+        .unwrap();
+    }
+    format!(r#"<select name="segment">{html}</select>"#)
+}
+
+#[tracing::instrument(name = "get segment summaries", skip(pool))]
+pub(crate) async fn get_segment_summaries(
+    pool: &PgPool,
+) -> Result<Vec<SegmentSummary>, anyhow::Error> {
+    let segments = sqlx::query!(r#"SELECT segment_id, name, filter FROM segments ORDER BY name"#)
+        .fetch_all(pool)
+        .await
+        .context("failed to retrieve the segments")?;
+    let mut summaries = Vec::with_capacity(segments.len());
+    for segment in segments {
+        // Segments are validated when they are saved
+        let filter = SegmentFilter::parse(&segment.filter).map_err(anyhow::Error::msg)?;
+        summaries.push(SegmentSummary {
+            n_matching: count_matching_subscribers(pool, &filter).await?,
+            segment_id: segment.segment_id,
+            name: segment.name,
+            filter: segment.filter,
+        });
+    }
+    Ok(summaries)
+}
+
+/// Confirmed subscribers, except the suppressed ones, matching `filter`.
+#[tracing::instrument(skip(pool))]
+async fn count_matching_subscribers(
+    pool: &PgPool,
+    filter: &SegmentFilter,
+) -> Result<i64, anyhow::Error> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT COUNT(*)
+        FROM subscriptions s
+        WHERE
+            s.status = 'confirmed' AND
+            s.email NOT IN (SELECT email FROM email_suppressions) AND
+            ("#,
+    );
+    filter.push_sql(&mut query);
+    query.push(")");
+    let (n_matching,) = query
+        .build_query_as::<(i64,)>()
+        .fetch_one(pool)
+        .await
+        .context("failed to count the subscribers matching the segment")?;
+    Ok(n_matching)
+}
@@ -0,0 +1,99 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use sqlx::PgPool;
+
+/// Subscribers are read, and sent, this many at a time.
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    status: Option<String>,
+}
+
+struct ExportedSubscriber {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+/// Where the export stands: the last email sent, if any.
+struct ExportCursor {
+    pool: PgPool,
+    status: Option<String>,
+    after: Option<String>,
+    done: bool,
+}
+
+/// Stream the subscribers as a CSV file, without loading them all in memory.
+#[tracing::instrument(name = "export subscribers", skip(query, pool))]
+pub async fn export_subscribers(
+    query: web::Query<ExportQuery>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let status = query.into_inner().status.filter(|s| !s.is_empty());
+    let cursor = ExportCursor {
+        pool: pool.get_ref().clone(),
+        status,
+        after: None,
+        done: false,
+    };
+    let header = stream::once(async {
+        Ok::<_, anyhow::Error>(Bytes::from_static(b"email,name,status,subscribed_at\n"))
+    });
+    let rows = stream::try_unfold(cursor, next_page);
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .streaming(header.chain(rows))
+}
+
+async fn next_page(
+    mut cursor: ExportCursor,
+) -> Result<Option<(Bytes, ExportCursor)>, anyhow::Error> {
+    if cursor.done {
+        return Ok(None);
+    }
+    // Keyset pagination: the export is not thrown off by concurrent changes
+    let subscribers = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR status = $1) AND
+            ($2::text IS NULL OR email > $2)
+        ORDER BY email
+        LIMIT $3
+        "#,
+        cursor.status,
+        cursor.after,
+        EXPORT_PAGE_SIZE,
+    )
+    .fetch_all(&cursor.pool)
+    .await
+    .context("failed to retrieve a page of subscribers to export")?;
+    if subscribers.is_empty() {
+        return Ok(None);
+    }
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for s in &subscribers {
+        writer.write_record([
+            s.email.as_str(),
+            s.name.as_str(),
+            s.status.as_str(),
+            &s.subscribed_at.to_rfc3339(),
+        ])?;
+    }
+    let chunk = writer.into_inner().context("failed to write the CSV")?;
+    cursor.done = (subscribers.len() as i64) < EXPORT_PAGE_SIZE;
+    cursor.after = subscribers.last().map(|s| s.email.clone());
+    Ok(Some((Bytes::from(chunk), cursor)))
+}
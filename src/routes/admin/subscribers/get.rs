@@ -0,0 +1,418 @@
+use crate::utils::{e404, e500};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const SUBSCRIBERS_PER_PAGE: i64 = 25;
+const STATUSES: [&str; 5] = [
+    "pending_confirmation",
+    "confirmed",
+    "unsubscribed",
+    "bounced",
+    "complained",
+];
+
+#[derive(serde::Deserialize)]
+pub struct SubscribersQuery {
+    /// Matched against both the email and the name
+    search: Option<String>,
+    status: Option<String>,
+    page: Option<i64>,
+}
+
+impl SubscribersQuery {
+    /// Empty form fields mean "no filter".
+    fn search(&self) -> Option<&str> {
+        self.search.as_deref().filter(|s| !s.trim().is_empty())
+    }
+
+    fn status(&self) -> Option<&str> {
+        self.status.as_deref().filter(|s| !s.is_empty())
+    }
+
+    fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    /// Link to another page of the listing, keeping the filters.
+    fn page_link(&self, page: i64) -> String {
+        format!(
+            "/admin/subscribers?search={}&status={}&page={}",
+            urlencoding::encode(self.search().unwrap_or_default()),
+            urlencoding::encode(self.status().unwrap_or_default()),
+            page
+        )
+    }
+}
+
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+struct SubscriptionToken {
+    subscription_token: String,
+    created_at: DateTime<Utc>,
+    expired_at: Option<DateTime<Utc>>,
+}
+
+pub async fn subscribers(
+    query: web::Query<SubscribersQuery>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let page = query.page();
+    let (subscribers, n_subscribers) = search_subscribers(&pool, &query, page)
+        .await
+        .map_err(e500)?;
+    let mut rows_html = String::new();
+    for s in subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td><a href="/admin/subscribers/{id}">{email}</a></td>
+            <td>{name}</td>
+            <td>{status}</td>
+            <td>{subscribed_at}</td>
+        </tr>"#,
+            id = s.id,
+            email = encode_minimal(&s.email),
+            name = encode_minimal(&s.name),
+            status = s.status,
+            subscribed_at = s.subscribed_at.format("%Y-%m-%d %H:%M UTC"),
+        )
+        .unwrap();
+    }
+
+    let mut status_options = String::from(r#"<option value="">Any status</option>"#);
+    for status in STATUSES {
+        let selected = if query.status() == Some(status) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            status_options,
+            r#"<option value="{status}"{selected}>{status}</option>"#
+        )
+        .unwrap();
+    }
+
+    let n_pages = ((n_subscribers + SUBSCRIBERS_PER_PAGE - 1) / SUBSCRIBERS_PER_PAGE).max(1);
+    let mut pagination_html = String::new();
+    if page > 1 {
+        write!(
+            pagination_html,
+            r#"<a href="{}">&lt; Previous</a> "#,
+            encode_attribute(&query.page_link(page - 1))
+        )
+        .unwrap();
+    }
+    write!(pagination_html, "Page {page} of {n_pages}").unwrap();
+    if page < n_pages {
+        write!(
+            pagination_html,
+            r#" <a href="{}">Next &gt;</a>"#,
+            encode_attribute(&query.page_link(page + 1))
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/subscribers" method="get">
+        <input type="text" placeholder="Search by email or name" name="search" value="{search}">
+        <select name="status">{status_options}</select>
+        <button type="submit">Search</button>
+    </form>
+    <p>{n_subscribers} subscriber(s)</p>
+    <p>
+        <a href="/admin/subscribers/import">Import from CSV</a>
+        <a href="{export_link}">Export to CSV</a>
+    </p>
+    <table>
+        <tr>
+            <th>Email</th>
+            <th>Name</th>
+            <th>Status</th>
+            <th>Subscribed at</th>
+        </tr>
+        {rows_html}
+    </table>
+    <p>{pagination_html}</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            search = encode_attribute(query.search().unwrap_or_default()),
+            export_link = encode_attribute(&format!(
+                "/admin/subscribers/export?status={}",
+                urlencoding::encode(query.status().unwrap_or_default())
+            )),
+        )))
+}
+
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = get_subscriber(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("the subscriber does not exist"))?;
+    let tokens = get_subscription_tokens(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    let (tags, attributes) = get_tags_and_attributes(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut tokens_html = String::new();
+    for t in tokens {
+        writeln!(
+            tokens_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&t.subscription_token),
+            t.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            t.expired_at.map_or_else(
+                || "-".to_string(),
+                |t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+            ),
+        )
+        .unwrap();
+    }
+    let mut actions_html = String::new();
+    let action = |html: &mut String, action: &str, label: &str| {
+        writeln!(
+            html,
+            r#"<form action="/admin/subscribers/{subscriber_id}/{action}" method="post">
+            <button type="submit">{label}</button>
+        </form>"#
+        )
+        .unwrap();
+    };
+    if subscriber.status != "confirmed" {
+        action(&mut actions_html, "confirm", "Confirm");
+    }
+    if subscriber.status != "unsubscribed" {
+        action(&mut actions_html, "unsubscribe", "Unsubscribe");
+    }
+    action(
+        &mut actions_html,
+        "delete",
+        "Delete the subscriber and all their data",
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    {msg_html}
+    <ul>
+        <li>Email: {email}</li>
+        <li>Name: {name}</li>
+        <li>Status: {status}</li>
+        <li>Subscribed at: {subscribed_at}</li>
+    </ul>
+    <p>Subscription tokens:</p>
+    <table>
+        <tr>
+            <th>Token</th>
+            <th>Created at</th>
+            <th>Expired at</th>
+        </tr>
+        {tokens_html}
+    </table>
+    <h2>Tags and attributes</h2>
+    <p>Used to select subscribers with <a href="/admin/segments">segments</a>.</p>
+    <form action="/admin/subscribers/{subscriber_id}/tags" method="post">
+        <label>Tags, separated by commas
+            <input type="text" name="tags" value="{tags}">
+        </label>
+        <button type="submit">Save tags</button>
+    </form>
+    <form action="/admin/subscribers/{subscriber_id}/attributes" method="post">
+        <label>Attributes, as a JSON object<br>
+            <textarea name="attributes" rows="6" cols="60">{attributes}</textarea>
+        </label><br>
+        <button type="submit">Save attributes</button>
+    </form>
+    {actions_html}
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            tags = encode_attribute(&tags.join(", ")),
+            attributes = encode_minimal(&attributes),
+            email = encode_minimal(&subscriber.email),
+            name = encode_minimal(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        )))
+}
+
+/// Returns a page of matching subscribers, and how many match overall.
+#[tracing::instrument(skip(pool, query))]
+async fn search_subscribers(
+    pool: &PgPool,
+    query: &SubscribersQuery,
+    page: i64,
+) -> Result<(Vec<Subscriber>, i64), anyhow::Error> {
+    let pattern = query
+        .search()
+        .map(|search| format!("%{}%", escape_like(search.trim())));
+    let status = query.status();
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        pattern,
+        status,
+        SUBSCRIBERS_PER_PAGE,
+        (page - 1) * SUBSCRIBERS_PER_PAGE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the subscribers")?;
+    let n_subscribers = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "n!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        "#,
+        pattern,
+        status,
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count the subscribers")?
+    .n;
+    Ok((subscribers, n_subscribers))
+}
+
+/// Search terms are matched literally.
+fn escape_like(search: &str) -> String {
+    search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve the subscriber")?;
+    Ok(subscriber)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscription_tokens(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<SubscriptionToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscription_token, created_at, expired_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at DESC
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the subscription tokens")?;
+    Ok(tokens)
+}
+
+/// The attributes are pretty-printed JSON.
+#[tracing::instrument(skip(pool))]
+async fn get_tags_and_attributes(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(Vec<String>, String), anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            jsonb_pretty(attributes) AS "attributes!",
+            ARRAY(
+                SELECT tag FROM subscriber_tags
+                WHERE subscriber_id = $1
+                ORDER BY tag
+            ) AS "tags!"
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to retrieve the tags and attributes of the subscriber")?;
+    Ok((row.tags, row.attributes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_like;
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"100%_sure\"), r"100\%\_sure\\");
+    }
+}
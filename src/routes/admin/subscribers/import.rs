@@ -0,0 +1,400 @@
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::routes::admin::lists::get_lists;
+use crate::routes::{
+    generate_subscription_token, get_list_id, send_confirmation_email, store_token, DEFAULT_LIST,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e400, e500};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Statuses that can be set through an import.
+const IMPORTABLE_STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+
+/// The largest import form accepted, in bytes: about 50,000 rows of a
+/// typical CSV, well past the 16 KB actix-web allows for forms by default.
+const IMPORT_FORM_LIMIT: usize = 4 * 1024 * 1024;
+
+pub fn import_form_config() -> web::FormConfig {
+    web::FormConfig::default().limit(IMPORT_FORM_LIMIT)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportFormData {
+    csv: String,
+    /// Checkbox: only sent by the browser when it is ticked
+    send_confirmation: Option<String>,
+    /// Slug of the list the subscribers are added to
+    list: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+    #[serde(default)]
+    status: Option<String>,
+}
+
+struct ValidRow {
+    line: u64,
+    subscriber: NewSubscriber,
+    status: Option<String>,
+}
+
+struct RowError {
+    line: u64,
+    message: String,
+}
+
+pub async fn import_subscribers_form(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut list_options = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        writeln!(
+            list_options,
+            r#"<option value="{}"{}>{}</option>"#,
+            encode_attribute(&list.slug),
+            if list.slug == DEFAULT_LIST {
+                " selected"
+            } else {
+                ""
+            },
+            encode_minimal(&list.name),
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    <form action="/admin/subscribers/import" method="post">
+        <label>Paste a CSV with an <code>email</code>, a <code>name</code>
+            and, optionally, a <code>status</code> column:<br>
+            <textarea placeholder="email,name,status" name="csv" rows="20" cols="80"></textarea>
+        </label>
+        <br>
+        <label>Add them to
+            <select name="list">{list_options}</select>
+        </label>
+        <br>
+        <label>
+            <input type="checkbox" name="send_confirmation" value="on">
+            Ask the new subscribers to confirm their subscription
+        </label>
+        <p>Without a status, new subscribers are imported as confirmed, or as
+            pending confirmation when confirmation emails are sent. The status of
+            existing subscribers is only changed by an explicit status.</p>
+        <button type="submit">Import</button>
+    </form>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "import subscribers",
+    skip(form, pool, email_client, base_url),
+    fields(send_confirmation = form.send_confirmation.is_some())
+)]
+pub async fn import_subscribers(
+    form: web::Form<ImportFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let send_confirmation = form.send_confirmation.is_some();
+    let (rows, mut errors) = parse_csv(&form.csv).map_err(e400)?;
+    let (rows, suppressed) = exclude_suppressed_addresses(&pool, rows)
+        .await
+        .map_err(e500)?;
+    errors.extend(suppressed);
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let list_slug = form.list.as_deref().unwrap_or(DEFAULT_LIST);
+    let list_id = get_list_id(&mut transaction, list_slug)
+        .await
+        .context("failed to look up the list")
+        .map_err(e500)?
+        .ok_or_else(|| e400(format!("{list_slug} is not a list")))?;
+    let default_status = if send_confirmation {
+        "pending_confirmation"
+    } else {
+        "confirmed"
+    };
+    let mut confirmations = Vec::new();
+    let n_imported = rows.len();
+    for row in rows {
+        let (subscriber_id, status) = upsert_subscriber(&mut transaction, &row, default_status)
+            .await
+            .context("failed to save an imported subscriber")
+            .map_err(e500)?;
+        add_to_list(&mut transaction, subscriber_id, list_id, &status)
+            .await
+            .context("failed to add an imported subscriber to the list")
+            .map_err(e500)?;
+        if send_confirmation && status == "pending_confirmation" {
+            let subscription_token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .context("failed to store the confirmation token of an imported subscriber")
+                .map_err(e500)?;
+            confirmations.push((row, subscription_token));
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("failed to commit the imported subscribers")
+        .map_err(e500)?;
+
+    for (row, subscription_token) in confirmations {
+        if let Err(e) = send_confirmation_email(
+            &email_client,
+            row.subscriber,
+            &base_url.0,
+            &subscription_token,
+        )
+        .await
+        {
+            tracing::error!(error.cause_chain = ?e, "failed to send a confirmation email");
+            errors.push(RowError {
+                line: row.line,
+                message: "imported, but the confirmation email could not be sent".into(),
+            });
+        }
+    }
+    errors.sort_by_key(|e| e.line);
+
+    let mut errors_html = String::new();
+    for e in &errors {
+        writeln!(
+            errors_html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            e.line,
+            encode_minimal(&e.message)
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    <p>{n_imported} subscriber(s) imported, {n_errors} problem(s) found.</p>
+    <table>
+        <tr>
+            <th>Line</th>
+            <th>Problem</th>
+        </tr>
+        {errors_html}
+    </table>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            n_errors = errors.len(),
+        )))
+}
+
+/// Validate every row of the CSV. Fails only if the CSV as a whole
+/// can't be used (e.g. missing columns).
+fn parse_csv(csv: &str) -> Result<(Vec<ValidRow>, Vec<RowError>), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("the CSV could not be read: {e}"))?
+        .clone();
+    for column in ["email", "name"] {
+        if !headers.iter().any(|h| h == column) {
+            return Err(format!("the CSV has no `{column}` column"));
+        }
+    }
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(RowError {
+                    line: e.position().map_or(0, |p| p.line()),
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        let outcome = record
+            .deserialize::<CsvRow>(Some(&headers))
+            .map_err(|e| e.to_string())
+            .and_then(|row| validate_row(line, row));
+        match outcome {
+            Ok(row) => rows.push(row),
+            Err(message) => errors.push(RowError { line, message }),
+        }
+    }
+    Ok((rows, errors))
+}
+
+fn validate_row(line: u64, row: CsvRow) -> Result<ValidRow, String> {
+    let email = SubscriberEmail::parse(row.email)?;
+    let name = SubscriberName::parse(row.name)?;
+    let status = row.status.filter(|s| !s.is_empty());
+    if let Some(status) = &status {
+        if !IMPORTABLE_STATUSES.contains(&status.as_str()) {
+            return Err(format!("{status} is not a valid status"));
+        }
+    }
+    Ok(ValidRow {
+        line,
+        subscriber: NewSubscriber { email, name },
+        status,
+    })
+}
+
+/// Addresses on the suppression list (hard bounces, spam complaints)
+/// must not be subscribed again.
+#[tracing::instrument(skip_all)]
+async fn exclude_suppressed_addresses(
+    pool: &PgPool,
+    rows: Vec<ValidRow>,
+) -> Result<(Vec<ValidRow>, Vec<RowError>), anyhow::Error> {
+    let emails: Vec<String> = rows
+        .iter()
+        .map(|r| r.subscriber.email.as_ref().to_owned())
+        .collect();
+    let suppressed: HashSet<String> = sqlx::query!(
+        r#"SELECT email FROM email_suppressions WHERE email = ANY($1)"#,
+        &emails,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the suppressed addresses")?
+    .into_iter()
+    .map(|r| r.email)
+    .collect();
+    let (rejected, rows): (Vec<_>, Vec<_>) = rows
+        .into_iter()
+        .partition(|r| suppressed.contains(r.subscriber.email.as_ref()));
+    let errors = rejected
+        .into_iter()
+        .map(|r| RowError {
+            line: r.line,
+            message: "the address is on the suppression list".into(),
+        })
+        .collect();
+    Ok((rows, errors))
+}
+
+/// Returns the id and the resulting status of the subscriber.
+#[tracing::instrument(skip_all)]
+async fn upsert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    row: &ValidRow,
+    default_status: &str,
+) -> Result<(Uuid, String), sqlx::Error> {
+    // Existing subscribers keep their status unless the CSV sets one:
+    // an import must not subscribe again someone who unsubscribed.
+    let subscriber = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, now(), COALESCE($4, $5))
+        ON CONFLICT (email) DO UPDATE
+        SET
+            name = EXCLUDED.name,
+            status = COALESCE($4, subscriptions.status)
+        RETURNING id, status
+        "#,
+        Uuid::new_v4(),
+        row.subscriber.email.as_ref(),
+        row.subscriber.name.as_ref(),
+        row.status,
+        default_status,
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok((subscriber.id, subscriber.status))
+}
+
+/// Confirmed subscribers are confirmed on the list right away, the
+/// others are confirmed on it along with their subscription.
+/// Somebody who left the list is not added back.
+#[tracing::instrument(skip(transaction))]
+async fn add_to_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    subscriber_status: &str,
+) -> Result<(), sqlx::Error> {
+    let status = if subscriber_status == "confirmed" {
+        "confirmed"
+    } else {
+        "pending_confirmation"
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (subscriber_id, list_id, status)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE
+        SET status = EXCLUDED.status
+        WHERE list_subscriptions.status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id,
+        status,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+
+    #[test]
+    fn every_invalid_row_is_reported_with_its_line() {
+        let csv = "email,name,status\n\
+            ursula@example.com,Ursula,confirmed\n\
+            not-an-email,Octavia,\n\
+            le.guin@example.com,,\n\
+            n.k@example.com,Nora,subscribed\n\
+            marge@example.com,Marge\n";
+        let (rows, errors) = parse_csv(csv).unwrap();
+        assert_eq!(rows.len(), 2);
+        let lines: Vec<u64> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn a_csv_without_the_required_columns_is_rejected() {
+        assert!(parse_csv("email,status\nursula@example.com,confirmed\n").is_err());
+    }
+}
@@ -0,0 +1,12 @@
+mod export;
+mod get;
+mod import;
+mod post;
+
+pub use export::export_subscribers;
+pub use get::{subscriber_details, subscribers};
+pub use import::{import_form_config, import_subscribers, import_subscribers_form};
+pub use post::{
+    confirm_subscriber_manually, delete_subscriber, set_subscriber_attributes, set_subscriber_tags,
+    unsubscribe_subscriber_manually,
+};
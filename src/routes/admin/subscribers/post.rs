@@ -0,0 +1,269 @@
+use crate::domain::is_valid_attribute_key;
+use crate::routes::{confirm_subscriber, unsubscribe_subscriber};
+use crate::utils::{e404, e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct TagsFormData {
+    /// Separated by commas
+    tags: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AttributesFormData {
+    /// A JSON object
+    attributes: String,
+}
+
+#[tracing::instrument(name = "manually confirm a subscriber", skip(pool))]
+pub async fn confirm_subscriber_manually(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    if !confirm_subscriber(&pool, subscriber_id)
+        .await
+        .context("failed to confirm the subscriber")
+        .map_err(e500)?
+    {
+        return Err(e404("the subscriber does not exist"));
+    }
+    FlashMessage::info("the subscriber has been confirmed").send();
+    Ok(see_other(&format!("/admin/subscribers/{subscriber_id}")))
+}
+
+#[tracing::instrument(name = "manually unsubscribe a subscriber", skip(pool))]
+pub async fn unsubscribe_subscriber_manually(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    if !unsubscribe_subscriber(&pool, subscriber_id)
+        .await
+        .context("failed to unsubscribe the subscriber")
+        .map_err(e500)?
+    {
+        return Err(e404("the subscriber does not exist"));
+    }
+    FlashMessage::info("the subscriber has been unsubscribed").send();
+    Ok(see_other(&format!("/admin/subscribers/{subscriber_id}")))
+}
+
+#[tracing::instrument(name = "delete a subscriber", skip(pool))]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !erase_subscriber(&pool, subscriber_id.into_inner())
+        .await
+        .context("failed to delete the subscriber")
+        .map_err(e500)?
+    {
+        return Err(e404("the subscriber does not exist"));
+    }
+    FlashMessage::info("the subscriber has been deleted").send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+/// Remove the subscriber and every trace of their email address
+/// (pending and past deliveries, opens and clicks).
+/// The suppression list is kept: it is what guarantees that a bounced
+/// or complaining address is never emailed again.
+/// Returns `false` if the subscriber does not exist.
+#[tracing::instrument(skip(pool))]
+async fn erase_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    // Opens and clicks are deleted in cascade
+    let Some(subscriber) = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = $1 RETURNING email"#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    else {
+        return Ok(false);
+    };
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        subscriber.email,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_dead_letters WHERE subscriber_email = $1"#,
+        subscriber.email,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM newsletter_issue_deliveries WHERE subscriber_email = $1"#,
+        subscriber.email,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "set the tags of a subscriber", skip(form, pool))]
+pub async fn set_subscriber_tags(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<TagsFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let details_url = format!("/admin/subscribers/{subscriber_id}");
+    let tags = match parse_tags(&form.tags) {
+        Ok(tags) => tags,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other(&details_url));
+        }
+    };
+    if !replace_tags(&pool, subscriber_id, &tags)
+        .await
+        .context("failed to save the tags of the subscriber")
+        .map_err(e500)?
+    {
+        return Err(e404("the subscriber does not exist"));
+    }
+    FlashMessage::info("the tags have been saved").send();
+    Ok(see_other(&details_url))
+}
+
+#[tracing::instrument(name = "set the attributes of a subscriber", skip(form, pool))]
+pub async fn set_subscriber_attributes(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<AttributesFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let details_url = format!("/admin/subscribers/{subscriber_id}");
+    let attributes = match parse_attributes(&form.attributes) {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other(&details_url));
+        }
+    };
+    let n_updated_rows = sqlx::query!(
+        r#"UPDATE subscriptions SET attributes = $2::text::jsonb WHERE id = $1"#,
+        subscriber_id,
+        attributes,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to save the attributes of the subscriber")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(e404("the subscriber does not exist"));
+    }
+    FlashMessage::info("the attributes have been saved").send();
+    Ok(see_other(&details_url))
+}
+
+/// Tags are trimmed and deduplicated, empty ones are dropped.
+fn parse_tags(tags: &str) -> Result<Vec<String>, String> {
+    let mut parsed = Vec::new();
+    for tag in tags.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        if tag.chars().count() > 50 {
+            return Err(format!("{tag} is longer than 50 characters"));
+        }
+        parsed.push(tag.to_owned());
+    }
+    parsed.sort();
+    parsed.dedup();
+    Ok(parsed)
+}
+
+/// Attributes must be a JSON object whose keys can be used in segment
+/// filters. An empty input clears them.
+fn parse_attributes(attributes: &str) -> Result<String, String> {
+    if attributes.trim().is_empty() {
+        return Ok("{}".into());
+    }
+    let attributes: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(attributes)
+            .map_err(|e| format!("the attributes are not a JSON object: {e}"))?;
+    if let Some(key) = attributes.keys().find(|k| !is_valid_attribute_key(k)) {
+        return Err(format!(
+            "{key} is not a valid attribute name: use letters, digits and underscores"
+        ));
+    }
+    Ok(serde_json::Value::Object(attributes).to_string())
+}
+
+/// Returns `false` if the subscriber does not exist.
+#[tracing::instrument(skip(pool))]
+async fn replace_tags(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    tags: &[String],
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let exists = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .is_some();
+    if !exists {
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"DELETE FROM subscriber_tags WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT $1, * FROM UNNEST($2::text[])
+        "#,
+        subscriber_id,
+        tags,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_attributes, parse_tags};
+    use claims::assert_err;
+
+    #[test]
+    fn tags_are_trimmed_and_deduplicated() {
+        assert_eq!(
+            parse_tags(" beta, early adopter,,beta ").unwrap(),
+            vec!["beta", "early adopter"]
+        );
+    }
+
+    #[test]
+    fn attributes_must_be_an_object_with_usable_keys() {
+        assert_eq!(
+            parse_attributes(r#"{"plan": "pro", "seats": 3}"#).unwrap(),
+            r#"{"plan":"pro","seats":3}"#
+        );
+        assert_eq!(parse_attributes("  ").unwrap(), "{}");
+        assert_err!(parse_attributes(r#"["pro"]"#));
+        assert_err!(parse_attributes(r#"{"billing plan": "pro"}"#));
+    }
+}
@@ -0,0 +1,270 @@
+use super::ApiError;
+use crate::authentication::UserId;
+use crate::configuration::IdempotencySettings;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_template::IssueTemplate;
+use crate::routes::newsletter::Content;
+use crate::routes::{
+    create_newsletter_issue, get_delivery_counts, get_engagement_counts, CreateIssueError,
+    DeliveryCounts, EngagementCounts, IssueContent, NewIssue,
+};
+use actix_web::http::header::LOCATION;
+use actix_web::web::ReqData;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct CreateIssueBody {
+    title: String,
+    content: Content,
+    /// Slugs of the lists the issue is sent to
+    lists: Vec<String>,
+    segment_id: Option<Uuid>,
+    /// Left out to publish the issue right away
+    scheduled_for: Option<DateTime<Utc>>,
+    #[serde(default)]
+    private: bool,
+}
+
+#[derive(serde::Serialize)]
+struct IssueSummary {
+    id: Uuid,
+    title: String,
+    status: String,
+    published_at: Option<DateTime<Utc>>,
+    scheduled_for: Option<DateTime<Utc>>,
+    private: bool,
+}
+
+#[derive(serde::Serialize)]
+struct IssueDetails {
+    #[serde(flatten)]
+    summary: IssueSummary,
+    lists: Vec<String>,
+    segment_id: Option<Uuid>,
+    content: IssueContentBody,
+}
+
+#[derive(serde::Serialize)]
+struct IssueContentBody {
+    markdown: Option<String>,
+    html: String,
+    text: String,
+}
+
+#[derive(serde::Serialize)]
+struct IssueStats {
+    deliveries: DeliveryCounts,
+    engagement: EngagementCounts,
+}
+
+/// Publish or schedule an issue, like the admin form does.
+///
+/// Requests must carry an `Idempotency-Key` header: retrying with the same
+/// key returns the response of the first request.
+#[tracing::instrument(
+    name = "create a newsletter issue through the API",
+    skip_all,
+    fields(user_id=%&*user_id)
+)]
+pub async fn create_issue(
+    request: HttpRequest,
+    body: web::Json<CreateIssueBody>,
+    pool: web::Data<PgPool>,
+    idempotency_settings: web::Data<IdempotencySettings>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+    let idempotency_key: IdempotencyKey = request
+        .headers()
+        .get("Idempotency-Key")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| ApiError::ValidationError("the 'Idempotency-Key' header is missing".into()))?
+        .to_owned()
+        .try_into()
+        .map_err(|e: anyhow::Error| ApiError::ValidationError(e.to_string()))?;
+    let CreateIssueBody {
+        title,
+        content,
+        lists,
+        segment_id,
+        scheduled_for,
+        private,
+    } = body.into_inner();
+    if lists.is_empty() {
+        return Err(ApiError::ValidationError(
+            "the issue must be sent to at least one list".into(),
+        ));
+    }
+    if scheduled_for.is_some_and(|t| t <= Utc::now()) {
+        return Err(ApiError::ValidationError(
+            "the scheduled date must be in the future".into(),
+        ));
+    }
+    let content = IssueContent::from(content);
+    // A broken template must be caught before any delivery is queued
+    IssueTemplate::validate(&title, &content.html_content, &content.text_content)
+        .map_err(ApiError::ValidationError)?;
+    let mut transaction = match try_processing(
+        &pool,
+        &idempotency_key,
+        *user_id,
+        idempotency_settings.retention(),
+    )
+    .await?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+    let issue = NewIssue {
+        draft_id: None,
+        title,
+        content,
+        scheduled_for,
+        lists,
+        segment_id,
+        is_private: private,
+    };
+    // Dropping the transaction on error releases the idempotency key.
+    let issue_id = create_newsletter_issue(&mut transaction, &issue)
+        .await
+        .map_err(|e| match e {
+            CreateIssueError::UnknownList | CreateIssueError::UnknownSegment => {
+                ApiError::ValidationError(e.to_string())
+            }
+            CreateIssueError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+        })?;
+    let summary = get_issue_summary(&mut transaction, issue_id)
+        .await?
+        .context("the newsletter issue was not stored")?;
+    let response = HttpResponse::Accepted()
+        .insert_header((LOCATION, format!("/api/v1/issues/{issue_id}")))
+        .json(summary);
+    let response = save_response(transaction, &idempotency_key, *user_id, response).await?;
+    Ok(response)
+}
+
+/// All issues, drafts included, the most recent first.
+#[tracing::instrument(name = "list newsletter issues through the API", skip(pool))]
+pub async fn list_issues(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            newsletter_issue_id AS id,
+            title,
+            status,
+            published_at,
+            scheduled_for,
+            is_private AS private
+        FROM newsletter_issues
+        ORDER BY COALESCE(published_at, scheduled_for) DESC NULLS FIRST, title
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("failed to retrieve the newsletter issues")?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "issues": issues })))
+}
+
+#[tracing::instrument(name = "get a newsletter issue through the API", skip(pool))]
+pub async fn get_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            status,
+            published_at,
+            scheduled_for,
+            is_private,
+            segment_id,
+            markdown_content,
+            html_content,
+            text_content,
+            ARRAY(
+                SELECT l.slug
+                FROM newsletter_issue_lists il
+                JOIN lists l ON l.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+                ORDER BY l.slug
+            ) AS "lists!"
+        FROM newsletter_issues i
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id.into_inner(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("failed to retrieve the newsletter issue")?
+    .ok_or_else(|| ApiError::NotFound("the newsletter issue does not exist".into()))?;
+    Ok(HttpResponse::Ok().json(IssueDetails {
+        summary: IssueSummary {
+            id: issue.newsletter_issue_id,
+            title: issue.title,
+            status: issue.status,
+            published_at: issue.published_at,
+            scheduled_for: issue.scheduled_for,
+            private: issue.is_private,
+        },
+        lists: issue.lists,
+        segment_id: issue.segment_id,
+        content: IssueContentBody {
+            markdown: issue.markdown_content,
+            html: issue.html_content,
+            text: issue.text_content,
+        },
+    }))
+}
+
+/// The counts shown on the report of the issue in the admin UI.
+#[tracing::instrument(
+    name = "get the stats of a newsletter issue through the API",
+    skip(pool)
+)]
+pub async fn get_issue_stats(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let issue_id = issue_id.into_inner();
+    if get_issue_summary(pool.get_ref(), issue_id).await?.is_none() {
+        return Err(ApiError::NotFound(
+            "the newsletter issue does not exist".into(),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(IssueStats {
+        deliveries: get_delivery_counts(&pool, issue_id).await?,
+        engagement: get_engagement_counts(&pool, issue_id).await?,
+    }))
+}
+
+async fn get_issue_summary(
+    executor: impl PgExecutor<'_>,
+    issue_id: Uuid,
+) -> Result<Option<IssueSummary>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            newsletter_issue_id AS id,
+            title,
+            status,
+            published_at,
+            scheduled_for,
+            is_private AS private
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("failed to retrieve the newsletter issue")?;
+    Ok(issue)
+}
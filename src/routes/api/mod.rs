@@ -0,0 +1,57 @@
+//! The JSON API, served under `/api/v1` to clients authenticated with an
+//! API token (see [`crate::authentication::reject_invalid_api_tokens`]).
+mod issues;
+mod subscribers;
+
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+
+pub use issues::{create_issue, get_issue, get_issue_stats, list_issues};
+pub use subscribers::list_subscribers;
+
+/// Errors are returned as `{"error": "<message>"}`.
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            // The error chain is logged, not exposed
+            ApiError::UnexpectedError(_) => "an unexpected error occurred".to_string(),
+            e => e.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(serde_json::json!({ "error": message }))
+    }
+}
+
+/// Malformed bodies are rejected with the same JSON errors as the handlers.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e, _| ApiError::ValidationError(e.to_string()).into())
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default()
+        .error_handler(|e, _| ApiError::ValidationError(e.to_string()).into())
+}
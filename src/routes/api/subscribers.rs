@@ -0,0 +1,77 @@
+use super::ApiError;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(serde::Deserialize)]
+pub struct Pagination {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    /// Slugs of the lists the subscriber has confirmed
+    lists: Vec<String>,
+}
+
+/// Subscribers in the order they subscribed, a page at a time.
+#[tracing::instrument(name = "list subscribers through the API", skip(pool, pagination))]
+pub async fn list_subscribers(
+    pagination: web::Query<Pagination>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = pagination.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::ValidationError(format!(
+            "the limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+    let offset = pagination.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(ApiError::ValidationError(
+            "the offset cannot be negative".into(),
+        ));
+    }
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.status,
+            s.subscribed_at,
+            ARRAY(
+                SELECT l.slug
+                FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id AND ls.status = 'confirmed'
+                ORDER BY l.slug
+            ) AS "lists!"
+        FROM subscriptions s
+        ORDER BY s.subscribed_at, s.id
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("failed to retrieve the subscribers")?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "subscribers": subscribers,
+        "limit": limit,
+        "offset": offset,
+    })))
+}
@@ -0,0 +1,156 @@
+use crate::issue_template::{IssueTemplate, TemplateContext};
+use crate::utils::{e404, e500};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct ArchivedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: Option<DateTime<Utc>>,
+}
+
+/// Build the link to the web version of an issue in the public archive.
+pub fn archive_link(base_url: &str, newsletter_issue_id: Uuid) -> String {
+    format!("{base_url}/archive/{newsletter_issue_id}")
+}
+
+pub async fn archive(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let mut issues_html = String::new();
+    for issue in get_archived_issues(&pool).await.map_err(e500)? {
+        writeln!(
+            issues_html,
+            r#"<li><a href="/archive/{}">{}</a> ({})</li>"#,
+            issue.newsletter_issue_id,
+            encode_minimal(&issue.title),
+            published_on(issue.published_at),
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Archive</title>
+    <link rel="alternate" type="application/rss+xml" href="/feed.rss">
+    <link rel="alternate" type="application/atom+xml" href="/feed.atom">
+</head>
+<body>
+    <h1>Past issues</h1>
+    <ul>
+        {issues_html}
+    </ul>
+    <p><a href="/">&lt;- Home</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn archived_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content, published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'published' AND NOT is_private
+        "#,
+        *issue_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("failed to retrieve the newsletter issue")
+    .map_err(e500)?
+    .ok_or_else(|| e404("the newsletter issue does not exist"))?;
+    let html_content = web_version(
+        *issue_id,
+        &issue.title,
+        &issue.html_content,
+        &issue.text_content,
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{published_on}</p>
+    {html_content}
+    <p><a href="/archive">&lt;- Past issues</a></p>
+</body>
+</html>"#,
+            title = encode_minimal(&issue.title),
+            published_on = published_on(issue.published_at),
+        )))
+}
+
+/// The HTML content of an issue, as shown on the web.
+fn render_web_version(
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<String, anyhow::Error> {
+    let rendered = IssueTemplate::parse(html_content, text_content)
+        .and_then(|template| template.render(&TemplateContext::archive(title)))
+        .context("failed to render the newsletter issue")?;
+    // The content is meant for inboxes: whatever could run in a browser
+    // is stripped before it is served from our domain.
+    Ok(ammonia::clean(&rendered.html_content))
+}
+
+/// Like [`render_web_version`], for issues that are already published:
+/// those published before they were templates may not render, their
+/// content is then shown as it was stored, sanitised all the same.
+pub(crate) fn web_version(
+    newsletter_issue_id: Uuid,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+) -> String {
+    render_web_version(title, html_content, text_content).unwrap_or_else(|e| {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            %newsletter_issue_id,
+            "showing the newsletter issue without rendering it"
+        );
+        ammonia::clean(html_content)
+    })
+}
+
+fn published_on(published_at: Option<DateTime<Utc>>) -> String {
+    published_at
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+#[tracing::instrument(name = "get archived issues", skip(pool))]
+async fn get_archived_issues(pool: &PgPool) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT newsletter_issue_id, title, published_at
+        FROM newsletter_issues
+        WHERE status = 'published' AND NOT is_private
+        ORDER BY published_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the archived newsletter issues")?;
+    Ok(issues)
+}
@@ -0,0 +1,193 @@
+use crate::routes::archive::{archive_link, web_version};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+use actix_web::http::header::{ETag, EntityTag, IfModifiedSince, IfNoneMatch, LastModified};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// How many issues the feeds list, starting from the latest one
+const FEED_LENGTH: i64 = 20;
+const FEED_TITLE: &str = "Newsletter";
+
+struct FeedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+/// Like the archive, feeds only list public issues.
+#[tracing::instrument(name = "rss feed", skip_all)]
+pub async fn rss_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let base_url = &base_url.0;
+    let issues = get_feed_issues(&pool).await.map_err(e500)?;
+    let mut items = Vec::with_capacity(issues.len());
+    for issue in &issues {
+        let permalink = archive_link(base_url, issue.newsletter_issue_id);
+        items.push(rss::Item {
+            title: Some(issue.title.clone()),
+            link: Some(permalink.clone()),
+            description: Some(web_version(
+                issue.newsletter_issue_id,
+                &issue.title,
+                &issue.html_content,
+                &issue.text_content,
+            )),
+            guid: Some(rss::Guid {
+                value: permalink,
+                permalink: true,
+            }),
+            pub_date: Some(issue.published_at.to_rfc2822()),
+            ..Default::default()
+        });
+    }
+    let channel = rss::Channel {
+        title: FEED_TITLE.into(),
+        link: format!("{base_url}/archive"),
+        description: "The latest issues of the newsletter".into(),
+        pub_date: issues.first().map(|i| i.published_at.to_rfc2822()),
+        items,
+        ..Default::default()
+    };
+    Ok(feed_response(
+        &request,
+        "application/rss+xml; charset=utf-8",
+        channel.to_string(),
+        issues.first().map(|i| i.published_at),
+    ))
+}
+
+#[tracing::instrument(name = "atom feed", skip_all)]
+pub async fn atom_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let base_url = &base_url.0;
+    let issues = get_feed_issues(&pool).await.map_err(e500)?;
+    let mut entries = Vec::with_capacity(issues.len());
+    for issue in &issues {
+        let permalink = archive_link(base_url, issue.newsletter_issue_id);
+        entries.push(atom_syndication::Entry {
+            title: issue.title.as_str().into(),
+            id: permalink.clone(),
+            updated: issue.published_at.into(),
+            published: Some(issue.published_at.into()),
+            links: vec![atom_syndication::Link {
+                href: permalink,
+                rel: "alternate".into(),
+                ..Default::default()
+            }],
+            content: Some(atom_syndication::Content {
+                value: Some(web_version(
+                    issue.newsletter_issue_id,
+                    &issue.title,
+                    &issue.html_content,
+                    &issue.text_content,
+                )),
+                content_type: Some("html".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+    let last_published_at = issues.first().map(|i| i.published_at);
+    let feed = atom_syndication::Feed {
+        title: FEED_TITLE.into(),
+        id: format!("{base_url}/feed.atom"),
+        // An empty feed has never been updated
+        updated: last_published_at.unwrap_or_default().into(),
+        links: vec![
+            atom_syndication::Link {
+                href: format!("{base_url}/feed.atom"),
+                rel: "self".into(),
+                ..Default::default()
+            },
+            atom_syndication::Link {
+                href: format!("{base_url}/archive"),
+                rel: "alternate".into(),
+                ..Default::default()
+            },
+        ],
+        entries,
+        ..Default::default()
+    };
+    Ok(feed_response(
+        &request,
+        "application/atom+xml; charset=utf-8",
+        feed.to_string(),
+        last_published_at,
+    ))
+}
+
+/// Feed readers poll: they get a `304 Not Modified` when the feed has not
+/// changed since they last fetched it.
+/// The entity tag is a hash of the feed, so that issues made private or
+/// public again are noticed too. `If-Modified-Since` is only looked at
+/// without `If-None-Match`, as required by RFC 9110.
+fn feed_response(
+    request: &HttpRequest,
+    content_type: &str,
+    body: String,
+    last_published_at: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(format!("{:x}", Sha256::digest(body.as_bytes())));
+    // HTTP dates have no fractions of a second
+    let last_modified = last_published_at.map(|t| SystemTime::from(t.trunc_subsecs(0)).into());
+    let not_modified = match request.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => match (request.get_header::<IfModifiedSince>(), last_modified) {
+            (Some(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        },
+    };
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
+
+#[tracing::instrument(name = "get feed issues", skip(pool))]
+async fn get_feed_issues(pool: &PgPool) -> Result<Vec<FeedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        FeedIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published' AND NOT is_private
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        FEED_LENGTH,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the issues of the feed")?;
+    Ok(issues)
+}
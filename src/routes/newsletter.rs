@@ -1,8 +1,12 @@
 use crate::authentication::{validate_credentials, AuthError, Credentials};
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
-use crate::routes::error_chain_fmt;
-use actix_web::http::header::{HeaderMap, HeaderValue};
+use crate::configuration::IdempotencySettings;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_template::IssueTemplate;
+use crate::routes::{
+    create_newsletter_issue, error_chain_fmt, CreateIssueError, IssueContent, NewIssue,
+    DEFAULT_LIST,
+};
+use actix_web::http::header::{HeaderMap, HeaderValue, LOCATION};
 use actix_web::http::{header, StatusCode};
 use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
@@ -14,6 +18,8 @@ use sqlx::PgPool;
 pub enum PublishError {
     #[error("authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -30,6 +36,7 @@ impl ResponseError for PublishError {
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
+            PublishError::ValidationError(e) => HttpResponse::BadRequest().body(e.clone()),
             PublishError::AuthError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
                 let header_vaue = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
@@ -50,96 +57,44 @@ pub struct BodyData {
     content: Content,
 }
 
+/// Either both bodies, or a Markdown source they are rendered from.
 #[derive(serde::Deserialize)]
-pub struct Content {
-    html: String,
-    text: String,
+#[serde(untagged)]
+pub enum Content {
+    Bodies { html: String, text: String },
+    Markdown { markdown: String },
 }
 
-// #[tracing::instrument(name = "validate credentials", skip(credentials, pool))]
-// async fn validate_credentials(
-//     credentials: Credentials,
-//     pool: &PgPool,
-// ) -> Result<uuid::Uuid, AuthError> {
-//     let mut user_id = None;
-//     let mut expected_password_hash = Secret::new(
-//         "$argon2id$v=19$m=15000,t=2,p=1$\
-//         gZiV/M1gPc22ElAH/Jh1Hw$\
-//         CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-//             .to_string(),
-//     );
-
-//     if let Some((stored_user_id, stored_password_hash)) =
-//         get_stored_credentials(&credentials.username, &pool).await?
-//     {
-//         user_id = Some(stored_user_id);
-//         expected_password_hash = stored_password_hash;
-//     }
-
-//     spawn_blocking_with_tracing(move || {
-//         verify_password_hash(expected_password_hash, credentials.password)
-//     })
-//     .await
-//     .context("failed to spawn blocking task")??;
-
-//     user_id
-//         .ok_or_else(|| anyhow::anyhow!("unknown username"))
-//         .map_err(AuthError::InvalidCredentials)
-// }
-
-// #[tracing::instrument(
-//     name = "verify password hash",
-//     skip(expected_password_hash, password_candidate)
-// )]
-// fn verify_password_hash(
-//     expected_password_hash: Secret<String>,
-//     password_candidate: Secret<String>,
-// ) -> Result<(), AuthError> {
-//     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
-//         .context("failed to parse hash in PHC string format")?;
-
-//     Argon2::default()
-//         .verify_password(
-//             password_candidate.expose_secret().as_bytes(),
-//             &expected_password_hash,
-//         )
-//         .context("invalid password")
-//         .map_err(AuthError::InvalidCredentials)
-// }
-
-// #[tracing::instrument(name = "get stored credentials", skip(username, pool))]
-// async fn get_stored_credentials(
-//     username: &str,
-//     pool: &PgPool,
-// ) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
-//     let row: Option<_> = sqlx::query!(
-//         r#"
-//         SELECT user_id, password_hash
-//         FROM users
-//         WHERE username = $1
-//         "#,
-//         username,
-//     )
-//     .fetch_optional(pool)
-//     .await
-//     .context("failed to perform a query to retrieve stored credentials")?
-//     .map(|row| (row.user_id, Secret::new(row.password_hash)));
-//     Ok(row)
-// }
+impl From<Content> for IssueContent {
+    fn from(content: Content) -> Self {
+        match content {
+            Content::Bodies { html, text } => IssueContent::new(None, html, text),
+            Content::Markdown { markdown } => {
+                IssueContent::new(Some(markdown), String::new(), String::new())
+            }
+        }
+    }
+}
 
+/// Publish an issue to the default list, for clients authenticated with
+/// Basic credentials. Deliveries are queued, like those of the admin form.
+///
+/// Requests may carry an `Idempotency-Key` header: retrying with the same
+/// key returns the response of the first request. Without one, every
+/// request publishes a new issue.
 #[tracing::instrument(
     name = "publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, idempotency_settings, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    idempotency_settings: web::Data<IdempotencySettings>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
     let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
     let user_id = validate_credentials(credentials, &pool)
         .await
@@ -147,43 +102,85 @@ pub async fn publish_newsletter(
             AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
             AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
         })?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let idempotency_key: Option<IdempotencyKey> = request
+        .headers()
+        .get("Idempotency-Key")
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| {
+                    PublishError::ValidationError(
+                        "the 'Idempotency-Key' header is not a valid UTF8 string".into(),
                     )
-                    .await
-                    .with_context(|| {
-                        format!("failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    // We record the error chain as a structured field
-                    // on the log record.
-                    error.cause_chain = ?error,
-                    "Skipping a confirmed subscriber. \
-                    Their stored contact details are valid",
-                );
+                })?
+                .to_owned()
+                .try_into()
+                .map_err(|e: anyhow::Error| PublishError::ValidationError(e.to_string()))
+        })
+        .transpose()?;
+    let BodyData { title, content } = body.into_inner();
+    let content = IssueContent::from(content);
+    // A broken template must be caught before any delivery is queued
+    IssueTemplate::validate(&title, &content.html_content, &content.text_content)
+        .map_err(PublishError::ValidationError)?;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(
+            &pool,
+            idempotency_key,
+            user_id,
+            idempotency_settings.retention(),
+        )
+        .await?
+        {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        },
+        None => pool
+            .begin()
+            .await
+            .context("failed to acquire a postgres connection from the pool")?,
+    };
+    let issue = NewIssue {
+        draft_id: None,
+        title,
+        content,
+        scheduled_for: None,
+        lists: vec![DEFAULT_LIST.to_owned()],
+        segment_id: None,
+        is_private: false,
+    };
+    let issue_id = create_newsletter_issue(&mut transaction, &issue)
+        .await
+        .map_err(|e| match e {
+            CreateIssueError::UnknownList | CreateIssueError::UnknownSegment => {
+                PublishError::UnexpectedError(e.into())
             }
+            CreateIssueError::UnexpectedError(e) => PublishError::UnexpectedError(e),
+        })?;
+    let status_url = format!("/api/v1/issues/{issue_id}");
+    let response = HttpResponse::Accepted()
+        .insert_header((LOCATION, status_url.as_str()))
+        .json(serde_json::json!({
+            "issue_id": issue_id,
+            "status_url": status_url,
+        }));
+    let response = match idempotency_key {
+        Some(idempotency_key) => {
+            save_response(transaction, &idempotency_key, user_id, response).await?
         }
-    }
-    Ok(HttpResponse::Ok().finish())
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("failed to commit the newsletter issue")?;
+            response
+        }
+    };
+    Ok(response)
 }
 
-// struct Credentials {
-//     username: String,
-//     password: Secret<String>,
-// }
-
 fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
     // The header value, if present, must be a valid UTF8 string
     let heade_value = headers
@@ -216,34 +213,3 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Erro
         password: Secret::new(password),
     })
 }
-
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
-#[tracing::instrument(name = "get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-    // We are returning a Vec of Result in the happy case.
-    // This allows the caller to bubble up errors due to network issues
-    // or other failures using the ? operator.
-    // See http://sled.rs/errors.html for a deep-dive about this technique.
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-
-    Ok(confirmed_subscribers)
-}
@@ -12,10 +12,15 @@ use sqlx::{PgPool, Postgres, Transaction};
 use tracing::Instrument;
 use uuid::Uuid;
 
+/// Slug of the list people join when they do not pick one.
+pub const DEFAULT_LIST: &str = "newsletter";
+
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Slug of the list to join
+    list: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -42,14 +47,54 @@ pub async fn subscribe(
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let form = form.into_inner();
+    let list_slug = form.list.clone().unwrap_or_else(|| DEFAULT_LIST.into());
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
     let mut transaction = pool
         .begin()
         .await
         .context("failed to acquire a postgres connection from the pool")?;
-    let subscriber_id = insert_subsriber(&mut transaction, &new_subscriber)
+    let list_id = get_list_id(&mut transaction, &list_slug)
+        .await
+        .context("failed to look up the list")?
+        .ok_or_else(|| SubscribeError::ValidationError(format!("{list_slug} is not a list")))?;
+    if is_suppressed(&mut transaction, &new_subscriber.email)
+        .await
+        .context("failed to look up the suppression list")?
+    {
+        return Err(SubscribeError::ValidationError(
+            "we are not allowed to send emails to this address".into(),
+        ));
+    }
+    let subscriber_id = match insert_subsriber(&mut transaction, &new_subscriber)
         .await
-        .context("failed to insert a new subscriber into the database")?;
+        .context("failed to insert a new subscriber into the database")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        // The email is already known: a pending subscriber, or a confirmed
+        // one joining another list, gets a fresh confirmation link.
+        // Somebody who left opts in again and has to confirm from scratch.
+        // A subscriber already confirmed on the list is left alone.
+        None => {
+            reopt_in(&mut transaction, &new_subscriber.email)
+                .await
+                .context("failed to opt a former subscriber back in")?;
+            match get_subscriber_awaiting_confirmation(
+                &mut transaction,
+                &new_subscriber.email,
+                list_id,
+            )
+            .await
+            .context("failed to look up an existing subscriber")?
+            {
+                Some(subscriber_id) => subscriber_id,
+                None => return Ok(HttpResponse::Ok().finish()),
+            }
+        }
+    };
+    join_list(&mut transaction, subscriber_id, list_id)
+        .await
+        .context("failed to add the subscriber to the list")?;
     let subscription_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
@@ -101,7 +146,7 @@ pub async fn send_confirmation_email(
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token,
@@ -120,6 +165,7 @@ pub async fn send_confirmation_email(
         .await
 }
 
+/// Returns `None` if a subscriber with the same email already exists.
 #[tracing::instrument(
     name = "saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -127,12 +173,13 @@ pub async fn send_confirmation_email(
 pub async fn insert_subsriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let inserted = sqlx::query!(
         r#"
     INSERT INTO subscriptions (id, email, name, subscribed_at, status)
     VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    ON CONFLICT (email) DO NOTHING
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
@@ -141,12 +188,118 @@ pub async fn insert_subsriber(
     )
     .execute(transaction)
     .await
+    .map_err(|e| {
+        tracing::error!("failed to execute query: {:?}", e);
+        e
+    })?
+    .rows_affected();
+
+    Ok((inserted == 1).then_some(subscriber_id))
+}
+
+#[tracing::instrument(
+    name = "get subscriber awaiting confirmation",
+    skip(transaction, email)
+)]
+async fn get_subscriber_awaiting_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    list_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT s.id FROM subscriptions s
+        LEFT JOIN list_subscriptions ls
+            ON ls.subscriber_id = s.id AND ls.list_id = $2
+        WHERE s.email = $1 AND (
+            s.status = 'pending_confirmation' OR
+            (s.status = 'confirmed' AND ls.status IS DISTINCT FROM 'confirmed')
+        )
+        FOR UPDATE OF s
+        "#,
+        email.as_ref(),
+        list_id,
+    )
+    .fetch_optional(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.map(|r| r.id))
+}
+
+/// Suppressed addresses are rejected before this is called,
+/// see [`is_suppressed`].
+#[tracing::instrument(name = "opt a former subscriber back in", skip(transaction, email))]
+async fn reopt_in(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'pending_confirmation'
+        WHERE email = $1 AND status NOT IN ('pending_confirmation', 'confirmed')
+        "#,
+        email.as_ref(),
+    )
+    .execute(transaction)
+    .await
     .map_err(|e| {
         tracing::error!("failed to execute query: {:?}", e);
         e
     })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "check if an address is suppressed", skip(transaction, email))]
+pub async fn is_suppressed(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email FROM email_suppressions WHERE email = $1"#,
+        email.as_ref(),
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "get list by slug", skip(transaction))]
+pub async fn get_list_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    slug: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(r#"SELECT list_id FROM lists WHERE slug = $1"#, slug)
+        .fetch_optional(transaction)
+        .await?;
+    Ok(row.map(|r| r.list_id))
+}
 
-    Ok(subscriber_id)
+/// The subscription to the list is confirmed along with the subscriber,
+/// see [`confirm_subscriber`](crate::routes::confirm_subscriber).
+/// A confirmed subscription is left untouched.
+#[tracing::instrument(name = "add subscriber to a list", skip(transaction))]
+pub async fn join_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (subscriber_id, list_id, status)
+        VALUES ($1, $2, 'pending_confirmation')
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE
+        SET status = 'pending_confirmation'
+        WHERE list_subscriptions.status <> 'confirmed'
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -212,7 +365,7 @@ pub fn error_chain_fmt(
 }
 
 // Generate a random 25-character-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,36 +1,133 @@
+use crate::configuration::SubscriptionSettings;
 use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use chrono::Utc;
+use sqlx::{Acquire, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+/// The SQLSTATE Postgres reports when a unique constraint is violated.
+const UNIQUE_VIOLATION: &str = "23505";
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
+#[tracing::instrument(
+    name = "confirm a pending subscriber",
+    skip(parameters, pool, settings)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+) -> HttpResponse {
+    let token_ttl = settings.confirmation_token_ttl();
+    let email_change =
+        match get_email_change_from_token(&pool, &parameters.subscription_token, token_ttl).await {
+            Ok(email_change) => email_change,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    if let Some((subscriber_id, new_email)) = email_change {
+        return match change_subscriber_email(
+            &pool,
+            subscriber_id,
+            &new_email,
+            &parameters.subscription_token,
+        )
+        .await
+        {
+            Ok(true) => HttpResponse::Ok().finish(),
+            // Somebody else subscribed with the new address in the meantime
+            Ok(false) => HttpResponse::Conflict().finish(),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        };
+    }
+    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token, token_ttl)
+        .await
+    {
         Ok(id) => id,
         Err(_) => return HttpResponse::InternalServerError().finish(),
     };
     match id {
         None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
+        Some(subscriber_id) => match confirm_pending_subscriber(&pool, subscriber_id).await {
+            Ok(true) => HttpResponse::Ok().finish(),
+            // The subscriber left after the link was sent
+            Ok(false) => HttpResponse::Unauthorized().finish(),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
     }
 }
 
-#[tracing::instrument(name = "mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+/// Unlike [`confirm_subscriber`], a subscriber who has left is not brought
+/// back: returns `false` unless they are pending or already confirmed.
+#[tracing::instrument(name = "mark pending subscriber as confirmed", skip(subscriber_id, pool))]
+async fn confirm_pending_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
     sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to execute query: {:?}", e);
+        e
+    })?;
+    let is_confirmed = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE id = $1 AND status = 'confirmed'"#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .is_some();
+    if !is_confirmed {
+        return Ok(false);
+    }
+    confirm_list_subscriptions(&mut transaction, subscriber_id).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+/// Their pending list subscriptions are confirmed as well.
+/// Returns `false` if the subscriber does not exist.
+#[tracing::instrument(name = "mark subscriber as confirmed", skip(subscriber_id, pool))]
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_updated_rows = sqlx::query!(
         r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
         subscriber_id,
     )
-    .execute(pool)
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to execute query: {:?}", e);
+        e
+    })?
+    .rows_affected();
+    confirm_list_subscriptions(&mut transaction, subscriber_id).await?;
+    transaction.commit().await?;
+    Ok(n_updated_rows > 0)
+}
+
+async fn confirm_list_subscriptions(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+    )
+    .execute(transaction)
     .await
     .map_err(|e| {
         tracing::error!("failed to execute query: {:?}", e);
@@ -39,15 +136,18 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()
     Ok(())
 }
 
+/// Tokens older than `token_ttl` are treated as if they did not exist.
 #[tracing::instrument(name = "get subscriber from token", skip(subscription_token, pool))]
 pub async fn get_subscriber_id_from_token(
     pool: &PgPool,
     subscription_token: &str,
+    token_ttl: chrono::Duration,
 ) -> Result<Option<Uuid>, sqlx::Error> {
     let result = sqlx::query!(
         "SELECT subscriber_id FROM subscription_tokens \
-        WHERE subscription_token = $1",
+        WHERE subscription_token = $1 AND created_at > $2 AND new_email IS NULL",
         subscription_token,
+        Utc::now() - token_ttl,
     )
     .fetch_optional(pool)
     .await
@@ -57,3 +157,74 @@ pub async fn get_subscriber_id_from_token(
     })?;
     Ok(result.map(|r| r.subscriber_id))
 }
+
+/// Look up a token confirming an email change, see [`get_subscriber_id_from_token`].
+#[tracing::instrument(name = "get email change from token", skip(subscription_token, pool))]
+pub async fn get_email_change_from_token(
+    pool: &PgPool,
+    subscription_token: &str,
+    token_ttl: chrono::Duration,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT subscriber_id, new_email AS "new_email!" FROM subscription_tokens
+        WHERE subscription_token = $1 AND created_at > $2 AND new_email IS NOT NULL
+        "#,
+        subscription_token,
+        Utc::now() - token_ttl,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| (r.subscriber_id, r.new_email)))
+}
+
+/// Returns `false` if the new email already belongs to another subscriber.
+///
+/// The token is consumed: an older link must not be able to revert a
+/// more recent change.
+#[tracing::instrument(
+    name = "change the email of a subscriber",
+    skip(pool, new_email, subscription_token)
+)]
+async fn change_subscriber_email(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    new_email: &str,
+    subscription_token: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // The unique constraint on the email decides, even when somebody
+    // subscribes with the same address concurrently. The update runs in a
+    // savepoint so that the token can still be consumed when it fails.
+    let mut savepoint = transaction.begin().await?;
+    let changed = match sqlx::query!(
+        r#"UPDATE subscriptions SET email = $2 WHERE id = $1"#,
+        subscriber_id,
+        new_email,
+    )
+    .execute(&mut savepoint)
+    .await
+    {
+        Ok(result) => {
+            savepoint.commit().await?;
+            result.rows_affected() > 0
+        }
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            savepoint.rollback().await?;
+            false
+        }
+        Err(e) => return Err(e),
+    };
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscription_token = $1",
+        subscription_token,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(changed)
+}
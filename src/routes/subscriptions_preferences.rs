@@ -0,0 +1,401 @@
+use crate::domain::{PreferencesToken, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::routes::{
+    error_chain_fmt, generate_subscription_token, is_suppressed, unsubscribe_link,
+};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::utils::see_other;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use actix_web_lab::extract::UrlEncodedForm;
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("the preferences link is invalid or has expired")]
+    InvalidToken(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// How long the link sent in an issue can be used to manage the
+/// subscription: it lets the bearer change the subscriber's email address.
+const PREFERENCES_LINK_VALIDITY: chrono::Duration = chrono::Duration::days(30);
+
+/// Build the link a subscriber can follow to manage their subscription.
+pub fn preferences_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    hmac_secret: &Secret<String>,
+) -> String {
+    let token = PreferencesToken::new(
+        subscriber_id,
+        Utc::now() + PREFERENCES_LINK_VALIDITY,
+        hmac_secret,
+    );
+    format!(
+        "{}/subscriptions/preferences?token={}",
+        base_url,
+        urlencoding::encode(token.as_ref())
+    )
+}
+
+struct Subscriber {
+    name: String,
+    email: String,
+    status: String,
+}
+
+struct ListChoice {
+    slug: String,
+    name: String,
+    chosen: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesFormData {
+    name: String,
+    email: String,
+    /// Slugs of the lists the subscriber wants to receive
+    #[serde(default)]
+    list: Vec<String>,
+}
+
+#[tracing::instrument(
+    name = "preferences form",
+    skip(parameters, pool, hmac_secret, flash_messages)
+)]
+pub async fn preferences_form(
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = PreferencesToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(PreferencesError::InvalidToken)?;
+    let subscriber = get_subscriber(&pool, subscriber_id)
+        .await
+        .context("failed to fetch the subscriber")?
+        .ok_or_else(|| PreferencesError::InvalidToken(anyhow::anyhow!("unknown subscriber")))?;
+    let lists = get_list_choices(&pool, subscriber_id)
+        .await
+        .context("failed to fetch the lists")?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let mut lists_html = String::new();
+    for list in &lists {
+        writeln!(
+            lists_html,
+            r#"<p><label><input type="checkbox" name="list" value="{}"{}> {}</label></p>"#,
+            htmlescape::encode_attribute(&list.slug),
+            if list.chosen { " checked" } else { "" },
+            htmlescape::encode_minimal(&list.name),
+        )
+        .unwrap();
+    }
+    let token = htmlescape::encode_attribute(&urlencoding::encode(&parameters.token));
+    let unsubscribe_html = if subscriber.status == "confirmed" {
+        // The unsubscribe route only accepts unsubscribe tokens
+        let action =
+            htmlescape::encode_attribute(&unsubscribe_link("", subscriber_id, &hmac_secret.0));
+        format!(
+            r#"<form action="{action}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>"#
+        )
+    } else {
+        "<p>You are not subscribed to our newsletter.</p>".to_string()
+    };
+    let name = htmlescape::encode_attribute(&subscriber.name);
+    let email = htmlescape::encode_attribute(&subscriber.email);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/subscriptions/preferences?token={token}" method="post">
+        <p><label>Name
+            <input type="text" name="name" value="{name}">
+        </label></p>
+        <p><label>Email
+            <input type="email" name="email" value="{email}">
+        </label></p>
+        <p>A change of email only takes effect once the new address is confirmed.</p>
+        <fieldset><legend>Lists</legend>
+            {lists_html}
+        </fieldset>
+        <button type="submit">Save</button>
+    </form>
+    {unsubscribe_html}
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "update subscriber preferences",
+    skip(parameters, form, pool, email_client, base_url, hmac_secret)
+)]
+pub async fn update_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    form: UrlEncodedForm<PreferencesFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = PreferencesToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(PreferencesError::InvalidToken)?;
+    let preferences_page = format!(
+        "/subscriptions/preferences?token={}",
+        urlencoding::encode(&parameters.token)
+    );
+    let PreferencesFormData {
+        name,
+        email,
+        list: lists,
+    } = form.into_inner();
+    let name = match SubscriberName::parse(name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&preferences_page));
+        }
+    };
+    let email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&preferences_page));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a postgres connection from the pool")?;
+    let Some(current_email) = save_preferences(&mut transaction, subscriber_id, &name, &lists)
+        .await
+        .context("failed to save the preferences of the subscriber")?
+    else {
+        return Err(PreferencesError::InvalidToken(anyhow::anyhow!(
+            "unknown subscriber"
+        )));
+    };
+    let email_change_token = if email.as_ref() == current_email {
+        None
+    } else if is_suppressed(&mut transaction, &email)
+        .await
+        .context("failed to check the suppression list")?
+    {
+        FlashMessage::error("we are not allowed to send emails to this address").send();
+        None
+    } else {
+        let token = generate_subscription_token();
+        store_email_change_token(&mut transaction, subscriber_id, &email, &token)
+            .await
+            .context("failed to store the email change token")?;
+        Some(token)
+    };
+    transaction
+        .commit()
+        .await
+        .context("failed to commit sql transaction to save preferences")?;
+
+    if let Some(token) = email_change_token {
+        send_email_change_confirmation(&email_client, &email, &base_url.0, &token)
+            .await
+            .context("failed to send the email change confirmation")?;
+        FlashMessage::info(format!(
+            "we sent a confirmation link to {}: your email will change once you follow it",
+            email.as_ref()
+        ))
+        .send();
+    }
+    FlashMessage::info("your preferences have been saved").send();
+    Ok(see_other(&preferences_page))
+}
+
+#[tracing::instrument(
+    name = "send a confirmation email to a new address",
+    skip(email_client, new_email, base_url)
+)]
+async fn send_email_change_confirmation(
+    email_client: &EmailClient,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token,
+    );
+    let plain_body = format!(
+        "Visit {} to receive our newsletter at this address.",
+        confirmation_link,
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to receive our newsletter at this address.",
+        confirmation_link,
+    );
+    email_client
+        .send_email(
+            new_email,
+            "Confirm your new email address",
+            &html_body,
+            &plain_body,
+        )
+        .await
+}
+
+#[tracing::instrument(name = "get subscriber", skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"SELECT name, email, status FROM subscriptions WHERE id = $1"#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "get lists of subscriber", skip(pool))]
+async fn get_list_choices(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListChoice>, sqlx::Error> {
+    sqlx::query_as!(
+        ListChoice,
+        r#"
+        SELECT l.slug, l.name, ls.status IS NOT DISTINCT FROM 'confirmed' AS "chosen!"
+        FROM lists l
+        LEFT JOIN list_subscriptions ls
+            ON ls.list_id = l.list_id AND ls.subscriber_id = $1
+        ORDER BY l.name
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the current email of the subscriber, `None` if they do not exist.
+/// Following the link is proof enough that the subscriber owns the address:
+/// the lists they pick are confirmed right away. Unknown lists are ignored.
+#[tracing::instrument(name = "save subscriber preferences", skip(transaction, name))]
+async fn save_preferences(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    list_slugs: &[String],
+) -> Result<Option<String>, sqlx::Error> {
+    let Some(subscriber) = sqlx::query!(
+        r#"UPDATE subscriptions SET name = $2 WHERE id = $1 RETURNING email"#,
+        subscriber_id,
+        name.as_ref(),
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(None);
+    };
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND list_id NOT IN (
+            SELECT list_id FROM lists WHERE slug = ANY($2)
+        )
+        "#,
+        subscriber_id,
+        list_slugs,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (subscriber_id, list_id, status)
+        SELECT $1, list_id, 'confirmed' FROM lists WHERE slug = ANY($2)
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE SET status = 'confirmed'
+        "#,
+        subscriber_id,
+        list_slugs,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(Some(subscriber.email))
+}
+
+/// Only the latest email change can be confirmed: older links are revoked.
+#[tracing::instrument(
+    name = "store email change token in the database",
+    skip(transaction, new_email, subscription_token)
+)]
+async fn store_email_change_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND new_email IS NOT NULL"#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, new_email)
+        VALUES ($1, $2, $3)
+        "#,
+        subscription_token,
+        subscriber_id,
+        new_email.as_ref(),
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::domain::UnsubscribeToken;
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("the unsubscribe link is invalid")]
+    InvalidToken(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Build the link a subscriber can follow to leave the newsletter.
+pub fn unsubscribe_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    hmac_secret: &Secret<String>,
+) -> String {
+    let token = UnsubscribeToken::new(subscriber_id, hmac_secret);
+    format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url,
+        urlencoding::encode(token.as_ref())
+    )
+}
+
+/// Ask for a confirmation before unsubscribing: mail clients and
+/// security scanners are known to follow links found in emails.
+#[tracing::instrument(name = "unsubscribe form", skip(parameters, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let token = htmlescape::encode_attribute(&urlencoding::encode(&parameters.token));
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?token={token}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+/// Also handles one-click unsubscriptions (RFC 8058): mail clients
+/// POST `List-Unsubscribe=One-Click` to the `List-Unsubscribe` URL.
+#[tracing::instrument(name = "unsubscribe a subscriber", skip(parameters, pool, hmac_secret))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    unsubscribe_subscriber(&pool, subscriber_id)
+        .await
+        .context("failed to unsubscribe the subscriber")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>You have been unsubscribed.</p>
+</body>
+</html>"#,
+    ))
+}
+
+/// Returns `false` if the subscriber does not exist.
+#[tracing::instrument(name = "mark subscriber as unsubscribed", skip(pool))]
+pub async fn unsubscribe_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let n_updated_rows = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_updated_rows > 0)
+}
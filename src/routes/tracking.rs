@@ -0,0 +1,183 @@
+use crate::domain::{TrackedAction, TrackedEvent, TrackingToken};
+use crate::startup::HmacSecret;
+use crate::utils::{e404, e500};
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION};
+use actix_web::{web, HttpResponse};
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A transparent 1x1 GIF.
+const TRACKING_PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+/// Route the links of an issue through `/t/c/{token}` and add
+/// a `/t/o/{token}` tracking pixel, for a single recipient.
+pub fn add_tracking(
+    html_content: &str,
+    base_url: &str,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    hmac_secret: &Secret<String>,
+) -> String {
+    let tracking_link = |action: TrackedAction, prefix: &str| {
+        let event = TrackedEvent {
+            newsletter_issue_id,
+            subscriber_id,
+            action,
+        };
+        let token = TrackingToken::new(&event, hmac_secret);
+        format!("{}/t/{}/{}", base_url, prefix, token.as_ref())
+    };
+    let mut html = rewrite_links(html_content, |url| {
+        // Leave `mailto:` links, anchors and relative links alone
+        (url.starts_with("http://") || url.starts_with("https://"))
+            .then(|| tracking_link(TrackedAction::Click(url.to_owned()), "c"))
+    });
+    let pixel = format!(
+        r#"<img src="{}" width="1" height="1" alt="">"#,
+        tracking_link(TrackedAction::Open, "o")
+    );
+    match html.rfind("</body>") {
+        Some(end_of_body) => html.insert_str(end_of_body, &pixel),
+        None => html.push_str(&pixel),
+    }
+    html
+}
+
+/// Replace the quoted `href` attributes of `html` for which `rewrite`
+/// returns a new URL. The replacements must not need escaping.
+fn rewrite_links(html: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(position) = rest.find("href=") {
+        let value_start = position + "href=".len();
+        let quote = match rest[value_start..].chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => {
+                output.push_str(&rest[..value_start]);
+                rest = &rest[value_start..];
+                continue;
+            }
+        };
+        let url_start = value_start + 1;
+        let Some(url_length) = rest[url_start..].find(quote) else {
+            break;
+        };
+        let url_end = url_start + url_length;
+        output.push_str(&rest[..url_start]);
+        let raw_url = &rest[url_start..url_end];
+        let url = htmlescape::decode_html(raw_url).unwrap_or_else(|_| raw_url.to_owned());
+        match rewrite(&url) {
+            Some(new_url) => output.push_str(&new_url),
+            None => output.push_str(raw_url),
+        }
+        rest = &rest[url_end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[tracing::instrument(name = "track an issue open", skip(token, pool, hmac_secret))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let event = verify_token(&token, &hmac_secret)?;
+    if event.action != TrackedAction::Open {
+        return Err(e404("unknown tracking token"));
+    }
+    record_event(&pool, &event).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(TRACKING_PIXEL))
+}
+
+#[tracing::instrument(name = "track a link click", skip(token, pool, hmac_secret))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let event = verify_token(&token, &hmac_secret)?;
+    let TrackedAction::Click(url) = &event.action else {
+        return Err(e404("unknown tracking token"));
+    };
+    record_event(&pool, &event).await.map_err(e500)?;
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url.as_str()))
+        .finish())
+}
+
+fn verify_token(token: &str, hmac_secret: &HmacSecret) -> Result<TrackedEvent, actix_web::Error> {
+    TrackingToken::verify(token, &hmac_secret.0).map_err(|e| {
+        tracing::warn!(error.message = %e, "invalid tracking token");
+        e404("unknown tracking token")
+    })
+}
+
+#[tracing::instrument(skip(pool))]
+async fn record_event(pool: &PgPool, event: &TrackedEvent) -> Result<(), sqlx::Error> {
+    let (kind, url) = match &event.action {
+        TrackedAction::Open => ("open", None),
+        TrackedAction::Click(url) => ("click", Some(url.as_str())),
+    };
+    // Events of subscribers who have since been removed are dropped
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_events (
+            newsletter_issue_id,
+            subscriber_id,
+            kind,
+            url,
+            occurred_at
+        )
+        SELECT $1, id, $3, $4, now()
+        FROM subscriptions
+        WHERE id = $2
+        "#,
+        event.newsletter_issue_id,
+        event.subscriber_id,
+        kind,
+        url,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rewrite_links;
+
+    fn rewrite(html: &str) -> String {
+        rewrite_links(html, |url| {
+            url.starts_with("https://")
+                .then(|| format!("https://tracker.test/{}", url.len()))
+        })
+    }
+
+    #[test]
+    fn absolute_links_are_rewritten() {
+        assert_eq!(
+            rewrite(r#"<a href="https://a.com">A</a> and <a href='https://bb.com'>B</a>"#),
+            r#"<a href="https://tracker.test/13">A</a> and <a href='https://tracker.test/14'>B</a>"#
+        );
+    }
+
+    #[test]
+    fn links_are_decoded_before_being_rewritten() {
+        assert_eq!(
+            rewrite(r#"<a href="https://a.com/?x=1&amp;y=2">A</a>"#),
+            r#"<a href="https://tracker.test/22">A</a>"#
+        );
+    }
+
+    #[test]
+    fn other_links_and_malformed_attributes_are_left_alone() {
+        let html =
+            r#"<a href="mailto:a@b.com">A</a><a href=https://a.com>B</a><a href="https://a.com"#;
+        assert_eq!(rewrite(html), html);
+    }
+}
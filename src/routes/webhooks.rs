@@ -0,0 +1,206 @@
+use crate::routes::error_chain_fmt;
+use crate::startup::WebhookSecret;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+/// Header carrying the shared secret, configured on the provider's side.
+const WEBHOOK_SECRET_HEADER: &str = "X-Webhook-Secret";
+
+/// Bounce and spam complaint notifications, as posted by Postmark.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct BounceNotification {
+    record_type: String,
+    /// The kind of bounce (`HardBounce`, `SoftBounce`, ...)
+    #[serde(rename = "Type", default)]
+    bounce_type: Option<String>,
+    email: String,
+    #[serde(default)]
+    description: Option<String>,
+    /// Set when the provider itself stopped delivering to the address
+    #[serde(default)]
+    inactive: bool,
+}
+
+/// Why an address ended up on the suppression list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SuppressionReason {
+    HardBounce,
+    SpamComplaint,
+}
+
+impl SuppressionReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::HardBounce => "hard_bounce",
+            SuppressionReason::SpamComplaint => "spam_complaint",
+        }
+    }
+
+    /// The status given to the matching subscriber.
+    fn subscription_status(&self) -> &'static str {
+        match self {
+            SuppressionReason::HardBounce => "bounced",
+            SuppressionReason::SpamComplaint => "complained",
+        }
+    }
+}
+
+impl BounceNotification {
+    /// Soft bounces and other transient failures do not suppress the address.
+    fn suppression_reason(&self) -> Option<SuppressionReason> {
+        match (self.record_type.as_str(), self.bounce_type.as_deref()) {
+            ("SpamComplaint", _) => Some(SuppressionReason::SpamComplaint),
+            ("Bounce", Some("HardBounce")) => Some(SuppressionReason::HardBounce),
+            ("Bounce", _) if self.inactive => Some(SuppressionReason::HardBounce),
+            _ => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("invalid webhook secret")]
+    InvalidSecret,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::InvalidSecret => StatusCode::UNAUTHORIZED,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "handle a bounce notification",
+    skip(request, notification, pool, secret),
+    fields(
+        record_type = %notification.record_type,
+        subscriber_email = %notification.email
+    )
+)]
+pub async fn email_bounce_webhook(
+    request: HttpRequest,
+    notification: web::Json<BounceNotification>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<WebhookSecret>,
+) -> Result<HttpResponse, WebhookError> {
+    verify_secret(&request, &secret)?;
+    let Some(reason) = notification.suppression_reason() else {
+        tracing::info!("the notification does not call for a suppression, ignoring it");
+        return Ok(HttpResponse::Ok().finish());
+    };
+    suppress_address(&pool, &notification, reason)
+        .await
+        .context("failed to suppress the email address")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn verify_secret(request: &HttpRequest, secret: &WebhookSecret) -> Result<(), WebhookError> {
+    let provided = request
+        .headers()
+        .get(WEBHOOK_SECRET_HEADER)
+        .ok_or(WebhookError::InvalidSecret)?;
+    // Compare digests rather than the secrets themselves,
+    // so that the comparison time does not leak the secret.
+    if Sha256::digest(provided.as_bytes()) != Sha256::digest(secret.0.expose_secret().as_bytes()) {
+        return Err(WebhookError::InvalidSecret);
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "suppress an email address", skip(pool, notification))]
+async fn suppress_address(
+    pool: &PgPool,
+    notification: &BounceNotification,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO email_suppressions (email, reason, details, suppressed_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        notification.email,
+        reason.as_str(),
+        notification.description,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = $2 WHERE email = $1"#,
+        notification.email,
+        reason.subscription_status(),
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BounceNotification, SuppressionReason};
+
+    fn notification(payload: serde_json::Value) -> BounceNotification {
+        serde_json::from_value(payload).unwrap()
+    }
+
+    #[test]
+    fn hard_bounces_and_spam_complaints_are_suppressed() {
+        let hard_bounce = notification(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "HardBounce",
+            "Email": "ursula@example.com",
+        }));
+        let complaint = notification(serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Type": "SpamComplaint",
+            "Email": "ursula@example.com",
+        }));
+        assert_eq!(
+            hard_bounce.suppression_reason(),
+            Some(SuppressionReason::HardBounce)
+        );
+        assert_eq!(
+            complaint.suppression_reason(),
+            Some(SuppressionReason::SpamComplaint)
+        );
+    }
+
+    #[test]
+    fn soft_bounces_are_not_suppressed_unless_the_address_is_inactive() {
+        let soft_bounce = notification(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "SoftBounce",
+            "Email": "ursula@example.com",
+            "Inactive": false,
+        }));
+        let deactivated = notification(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "SoftBounce",
+            "Email": "ursula@example.com",
+            "Inactive": true,
+        }));
+        assert_eq!(soft_bounce.suppression_reason(), None);
+        assert_eq!(
+            deactivated.suppression_reason(),
+            Some(SuppressionReason::HardBounce)
+        );
+    }
+}
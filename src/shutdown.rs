@@ -0,0 +1,29 @@
+use tokio_util::sync::CancellationToken;
+
+/// Cancel `shutdown` as soon as the process is asked to stop,
+/// either with SIGTERM (e.g. by the orchestrator) or with Ctrl-C.
+pub async fn cancel_on_shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+        // Another component is already shutting the application down
+        _ = shutdown.cancelled() => return,
+    }
+    tracing::info!("shutdown signal received, stopping gracefully");
+    shutdown.cancel();
+}
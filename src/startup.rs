@@ -1,9 +1,19 @@
-use crate::authentication::reject_anonymous_users;
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::authentication::{reject_anonymous_users, reject_invalid_api_tokens};
+use crate::configuration::{
+    DatabaseSettings, EmailClientSettings, IdempotencySettings, Settings, SubscriptionSettings,
+};
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletter, publish_newsletter_form, subscribe,
+    admin_dashboard, api, api_tokens, archive, archived_issue, atom_feed, cancel_scheduled_issue,
+    change_password, change_password_form, confirm, confirm_subscriber_manually, create_api_token,
+    create_draft, create_list, create_segment, dead_letters, delete_subscriber, drafts,
+    edit_draft_form, email_bounce_webhook, export_subscribers, health_check, home,
+    import_form_config, import_subscribers, import_subscribers_form, lists, log_out, login,
+    login_form, newsletter, newsletter_issue_report, preferences_form, preview_draft,
+    publish_draft, publish_newsletter, publish_newsletter_form, requeue_dead_letter,
+    reschedule_issue, revoke_api_token, rss_feed, scheduled_issues, segments, send_test_email,
+    set_issue_visibility, set_subscriber_attributes, set_subscriber_tags, subscribe,
+    subscriber_details, subscribers, track_click, track_open, unsubscribe, unsubscribe_form,
+    unsubscribe_subscriber_manually, update_draft, update_preferences,
 };
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
@@ -19,18 +29,26 @@ use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client_settings: EmailClientSettings,
+    subscription_settings: SubscriptionSettings,
+    idempotency_settings: IdempotencySettings,
     base_url: String,
     hmac_secret: Secret<String>,
     redis_uri: Secret<String>,
 ) -> Result<Server, anyhow::Error> {
     let db_pool = Data::new(db_pool);
-    let email_client = Data::new(email_client);
+    let rate_limiter = Data::new(email_client_settings.rate_limit.rate_limiter());
+    let webhook_secret = Data::new(WebhookSecret(email_client_settings.webhook_secret.clone()));
+    let email_client = Data::new(email_client_settings.client());
+    let subscription_settings = Data::new(subscription_settings);
+    let idempotency_settings = Data::new(idempotency_settings);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
@@ -50,24 +68,153 @@ pub async fn run(
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .route("/health_check", web::get().to(health_check))
-            .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/archive", web::get().to(archive))
+            .route("/archive/{issue_id}", web::get().to(archived_issue))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/feed.atom", web::get().to(atom_feed))
+            .route(
+                "/newsletters",
+                web::post().to(newsletter::publish_newsletter),
+            )
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/preferences",
+                web::get().to(preferences_form),
+            )
+            .route(
+                "/subscriptions/preferences",
+                web::post().to(update_preferences),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route(
+                "/webhooks/email/bounce",
+                web::post().to(email_bounce_webhook),
+            )
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/newsletters", web::get().to(publish_newsletter_form))
                     .route("/newsletters", web::post().to(publish_newsletter))
+                    .route("/newsletters/drafts", web::get().to(drafts))
+                    .route("/newsletters/drafts", web::post().to(create_draft))
+                    .route(
+                        "/newsletters/drafts/{draft_id}",
+                        web::get().to(edit_draft_form),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}",
+                        web::post().to(update_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}/preview",
+                        web::get().to(preview_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}/test",
+                        web::post().to(send_test_email),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}/publish",
+                        web::post().to(publish_draft),
+                    )
+                    .route("/newsletters/scheduled", web::get().to(scheduled_issues))
+                    .route(
+                        "/newsletters/{issue_id}/cancel",
+                        web::post().to(cancel_scheduled_issue),
+                    )
+                    .route(
+                        "/newsletters/{issue_id}/reschedule",
+                        web::post().to(reschedule_issue),
+                    )
+                    .route(
+                        "/newsletters/{issue_id}/visibility",
+                        web::post().to(set_issue_visibility),
+                    )
+                    .route(
+                        "/newsletters/{issue_id}",
+                        web::get().to(newsletter_issue_report),
+                    )
+                    .route("/subscribers", web::get().to(subscribers))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .service(
+                        web::resource("/subscribers/import")
+                            .app_data(import_form_config())
+                            .route(web::get().to(import_subscribers_form))
+                            .route(web::post().to(import_subscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_details),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(confirm_subscriber_manually),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        web::post().to(unsubscribe_subscriber_manually),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/delete",
+                        web::post().to(delete_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/tags",
+                        web::post().to(set_subscriber_tags),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/attributes",
+                        web::post().to(set_subscriber_attributes),
+                    )
+                    .route("/lists", web::get().to(lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/segments", web::get().to(segments))
+                    .route("/segments", web::post().to(create_segment))
+                    .route("/dead_letters", web::get().to(dead_letters))
+                    .route("/dead_letters/requeue", web::post().to(requeue_dead_letter))
+                    .route("/api_tokens", web::get().to(api_tokens))
+                    .route("/api_tokens", web::post().to(create_api_token))
+                    .route(
+                        "/api_tokens/{token_id}/revoke",
+                        web::post().to(revoke_api_token),
+                    )
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(log_out)),
             )
+            .service(
+                web::scope("/api/v1")
+                    .wrap(from_fn(reject_invalid_api_tokens))
+                    .app_data(api::json_config())
+                    .app_data(api::query_config())
+                    .route("/issues", web::get().to(api::list_issues))
+                    .route("/issues", web::post().to(api::create_issue))
+                    .route("/issues/{issue_id}", web::get().to(api::get_issue))
+                    .route(
+                        "/issues/{issue_id}/stats",
+                        web::get().to(api::get_issue_stats),
+                    )
+                    .route("/subscribers", web::get().to(api::list_subscribers)),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(idempotency_settings.clone())
             .app_data(base_url.clone())
             .app_data(Data::new(HmacSecret(hmac_secret.clone())))
+            .app_data(webhook_secret.clone())
     })
+    // Signals are handled by the caller, see `Application::run_until_stopped`
+    .disable_signals()
     .listen(listener)?
     .run();
     Ok(server)
@@ -76,6 +223,9 @@ pub async fn run(
 #[derive(Clone, Debug)]
 pub struct HmacSecret(pub Secret<String>);
 
+#[derive(Clone, Debug)]
+pub struct WebhookSecret(pub Secret<String>);
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
@@ -90,7 +240,6 @@ pub struct Application {
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let email_client = configuration.email_client.client();
 
         let address = format!(
             "{}:{}",
@@ -101,7 +250,9 @@ impl Application {
         let server = run(
             listener,
             connection_pool,
-            email_client,
+            configuration.email_client,
+            configuration.subscriptions,
+            configuration.idempotency,
             configuration.application.base_url,
             configuration.application.hmac_secret,
             configuration.redis_uri,
@@ -115,7 +266,17 @@ impl Application {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+    /// Serve requests until `shutdown` is cancelled, then stop accepting
+    /// connections and let in-flight requests complete.
+    pub async fn run_until_stopped(
+        self,
+        shutdown: CancellationToken,
+    ) -> Result<(), std::io::Error> {
+        let handle = self.server.handle();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            handle.stop(true).await;
+        });
         self.server.await
     }
 }
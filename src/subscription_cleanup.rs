@@ -0,0 +1,106 @@
+use crate::configuration::{Settings, SubscriptionSettings};
+use crate::startup::get_connection_pool;
+use chrono::Utc;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+pub async fn run_cleanup_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    cleanup_loop(&connection_pool, &configuration.subscriptions, shutdown).await
+}
+
+async fn cleanup_loop(
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        // Errors are already logged by `delete_stale_subscriptions`; try again on the next tick.
+        let _ = delete_stale_subscriptions(pool, settings).await;
+        tokio::select! {
+            _ = shutdown.cancelled() => {},
+            _ = tokio::time::sleep(settings.cleanup_interval()) => {},
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CleanupOutcome {
+    pub n_expired_tokens: u64,
+    pub n_deleted_tokens: u64,
+    pub n_abandoned_subscribers: u64,
+}
+
+/// Mark the confirmation tokens past their TTL as expired, then delete the
+/// pending subscribers who signed up long ago and are left without a valid
+/// token, along with their tokens.
+///
+/// Expired tokens are the token history shown on the details page of the
+/// subscriber in the admin area: they are only deleted once they have been
+/// expired for longer than the retention period.
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_stale_subscriptions(
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+) -> Result<CleanupOutcome, anyhow::Error> {
+    let now = Utc::now();
+    let mut transaction = pool.begin().await?;
+    let n_expired_tokens = sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET expired_at = $1
+        WHERE expired_at IS NULL AND created_at <= $2
+        "#,
+        now,
+        now - settings.confirmation_token_ttl(),
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    let n_deleted_tokens = sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE expired_at <= $1"#,
+        now - settings.expired_token_retention(),
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    let n_abandoned_subscribers = sqlx::query!(
+        r#"
+        WITH abandoned AS (
+            SELECT id FROM subscriptions
+            WHERE status = 'pending_confirmation'
+                AND subscribed_at <= $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM subscription_tokens
+                    WHERE subscriber_id = subscriptions.id AND expired_at IS NULL
+                )
+        ), deleted_tokens AS (
+            DELETE FROM subscription_tokens
+            WHERE subscriber_id IN (SELECT id FROM abandoned)
+        )
+        DELETE FROM subscriptions
+        WHERE id IN (SELECT id FROM abandoned)
+        "#,
+        now - settings.abandoned_after(),
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+    if n_expired_tokens > 0 || n_deleted_tokens > 0 || n_abandoned_subscribers > 0 {
+        tracing::info!(
+            n_expired_tokens,
+            n_deleted_tokens,
+            n_abandoned_subscribers,
+            "cleaned up stale subscriptions"
+        );
+    }
+    Ok(CleanupOutcome {
+        n_expired_tokens,
+        n_deleted_tokens,
+        n_abandoned_subscribers,
+    })
+}
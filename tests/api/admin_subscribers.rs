@@ -0,0 +1,371 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, AcceptBatch, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::subscription_cleanup::delete_stale_subscriptions;
+
+async fn insert_subscriber(app: &TestApp, email: &str, name: &str, status: &str) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, now(), $4)
+        "#,
+        subscriber_id,
+        email,
+        name,
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+async fn subscriber_status(app: &TestApp, subscriber_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+
+    // Act
+    let listing = app
+        .api_client
+        .get(format!("{}/admin/subscribers", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let deletion = app.post_subscriber_action(&subscriber_id, "delete").await;
+
+    // Assert
+    assert_is_redirect_to(&listing, "/login");
+    assert_is_redirect_to(&deletion, "/login");
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula Le Guin", "confirmed").await;
+    insert_subscriber(&app, "le.guin@example.com", "Ursula", "unsubscribed").await;
+    insert_subscriber(&app, "octavia@example.com", "Octavia Butler", "confirmed").await;
+
+    // Act
+    let by_name = app.get_subscribers_html("search=guin").await;
+    let by_status = app.get_subscribers_html("status=confirmed").await;
+    let both = app
+        .get_subscribers_html("search=guin&status=unsubscribed")
+        .await;
+
+    // Assert
+    assert!(by_name.contains("ursula@example.com"));
+    assert!(by_name.contains("le.guin@example.com"));
+    assert!(!by_name.contains("octavia@example.com"));
+    assert!(by_status.contains("ursula@example.com"));
+    assert!(by_status.contains("octavia@example.com"));
+    assert!(!by_status.contains("le.guin@example.com"));
+    assert!(both.contains("<p>1 subscriber(s)</p>"));
+    assert!(both.contains("le.guin@example.com"));
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for i in 0..30 {
+        let email = format!("subscriber{i}@example.com");
+        insert_subscriber(&app, &email, "A subscriber", "confirmed").await;
+    }
+
+    // Act
+    let first_page = app.get_subscribers_html("").await;
+    let second_page = app.get_subscribers_html("page=2").await;
+
+    // Assert
+    assert_eq!(first_page.matches("@example.com</a>").count(), 25);
+    assert!(first_page.contains("Page 1 of 2"));
+    assert!(first_page.contains("Next &gt;"));
+    assert_eq!(second_page.matches("@example.com</a>").count(), 5);
+    assert!(second_page.contains("Page 2 of 2"));
+    assert!(second_page.contains("&lt; Previous"));
+}
+
+#[tokio::test]
+async fn subscriber_details_show_the_token_history() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = sqlx::query!("SELECT subscriber_id, subscription_token FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Recently expired tokens are kept by the cleanup job
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '60 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    delete_stale_subscriptions(&app.db_pool, &app.configuration.subscriptions)
+        .await
+        .unwrap();
+
+    // Act
+    let html_page = app.get_subscriber_details_html(&token.subscriber_id).await;
+
+    // Assert
+    assert!(html_page.contains("<li>Status: confirmed</li>"));
+    assert!(html_page.contains(&token.subscription_token));
+    assert!(!html_page.contains("<td>-</td>"));
+}
+
+#[tokio::test]
+async fn the_details_of_an_unknown_subscriber_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscriber_details(&Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_confirmed_and_unsubscribed_manually() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id =
+        insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation").await;
+    let details = format!("/admin/subscribers/{subscriber_id}");
+
+    // Act - Part 1 - Confirm
+    let response = app.post_subscriber_action(&subscriber_id, "confirm").await;
+    assert_is_redirect_to(&response, &details);
+    let html_page = app.get_subscriber_details_html(&subscriber_id).await;
+    assert!(html_page.contains("<p><i>the subscriber has been confirmed</i></p>"));
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+
+    // Act - Part 2 - Unsubscribe
+    let response = app
+        .post_subscriber_action(&subscriber_id, "unsubscribe")
+        .await;
+    assert_is_redirect_to(&response, &details);
+    let html_page = app.get_subscriber_details_html(&subscriber_id).await;
+    assert!(html_page.contains("<p><i>the subscriber has been unsubscribed</i></p>"));
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_erases_all_their_data() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act
+    let response = app.post_subscriber_action(&subscriber_id, "delete").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_subscribers_html("").await;
+    assert!(html_page.contains("<p><i>the subscriber has been deleted</i></p>"));
+    let leftovers = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions) AS "subscriptions!",
+            (SELECT COUNT(*) FROM subscription_tokens) AS "tokens!",
+            (SELECT COUNT(*) FROM newsletter_issue_deliveries) AS "deliveries!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(leftovers.subscriptions, 0);
+    assert_eq!(leftovers.tokens, 0);
+    assert_eq!(leftovers.deliveries, 0);
+    let response = app.get_subscriber_details(&subscriber_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn valid_rows_are_imported_and_invalid_ones_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name,status\n\
+        ursula@example.com,Ursula Le Guin,\n\
+        not-an-email,Octavia Butler,\n\
+        n.k@example.com,N. K. Jemisin,unsubscribed\n\
+        marge@example.com,Marge Piercy,subscribed\n";
+
+    // Act
+    let response = app
+        .post_import_subscribers(&serde_json::json!({ "csv": csv }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p>2 subscriber(s) imported, 2 problem(s) found.</p>"));
+    assert!(html_page.contains("<tr><td>3</td>"));
+    assert!(html_page.contains("<tr><td>5</td>"));
+    let subscribers = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 2);
+    assert_eq!(subscribers[0].email, "n.k@example.com");
+    assert_eq!(subscribers[0].status, "unsubscribed");
+    assert_eq!(subscribers[1].email, "ursula@example.com");
+    assert_eq!(subscribers[1].status, "confirmed");
+}
+
+#[tokio::test]
+async fn imports_larger_than_the_default_form_limit_are_accepted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..500 {
+        csv.push_str(&format!(
+            "subscriber-{i}@example.com,Subscriber number {i}\n"
+        ));
+    }
+    assert!(csv.len() > 16 * 1024);
+
+    // Act
+    let response = app
+        .post_import_subscribers(&serde_json::json!({ "csv": csv }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p>500 subscriber(s) imported, 0 problem(s) found.</p>"));
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_subscribers, 500);
+}
+
+#[tokio::test]
+async fn imported_subscribers_can_be_asked_to_confirm() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nursula@example.com,Ursula\noctavia@example.com,Octavia\n";
+
+    // Act
+    app.post_import_subscribers(&serde_json::json!({
+        "csv": csv,
+        "send_confirmation": "on",
+    }))
+    .await;
+
+    // Assert
+    let n_pending = sqlx::query!(
+        r#"SELECT COUNT(*) AS "n!" FROM subscriptions WHERE status = 'pending_confirmation'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .n;
+    assert_eq!(n_pending, 2);
+    // Mock verifies on Drop that the confirmation emails have been sent
+}
+
+#[tokio::test]
+async fn importing_an_existing_subscriber_keeps_their_status() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id =
+        insert_subscriber(&app, "ursula@example.com", "Ursula", "unsubscribed").await;
+
+    // Act
+    app.post_import_subscribers(&serde_json::json!({
+        "csv": "email,name\nursula@example.com,Ursula Le Guin\n",
+    }))
+    .await;
+
+    // Assert
+    let subscriber = sqlx::query!(
+        "SELECT name, status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(subscriber.name, "Ursula Le Guin");
+    assert_eq!(subscriber.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula, Le Guin", "confirmed").await;
+    insert_subscriber(&app, "octavia@example.com", "Octavia", "unsubscribed").await;
+
+    // Act
+    let response = app.get_subscribers_export("status=confirmed").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "email,name,status,subscribed_at");
+    assert!(lines[1].starts_with(r#"ursula@example.com,"Ursula, Le Guin",confirmed,"#));
+}
@@ -0,0 +1,318 @@
+use crate::helpers::{
+    assert_is_redirect_to, issue_recipients, publish_issue, spawn_app, subscribe_to_list,
+    AcceptBatch, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn mount_email_api(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn n_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_api_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    // A session is not enough
+    app.test_user.login(&app).await;
+
+    let test_cases = vec![
+        (None, "missing token"),
+        (Some("z2p_unknown".to_string()), "unknown token"),
+    ];
+    for (token, description) in test_cases {
+        // Act
+        let mut request = app
+            .api_client
+            .get(format!("{}/api/v1/issues", &app.address));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.expect("failed to execute request");
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "the API did not reject a request with a {description}"
+        );
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+
+    // Basic credentials are not accepted either
+    let response = app
+        .api_client
+        .get(format!("{}/api/v1/issues", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn api_tokens_are_shown_once_and_can_be_revoked() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Create a token
+    let response = app.post_create_api_token("CI pipeline").await;
+
+    // Assert - The token is shown once, only its hash is stored
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let cookies: Vec<String> = response
+        .cookies()
+        .map(|c| urlencoding::decode(c.value()).unwrap().into_owned())
+        .collect();
+    let html_page = response.text().await.unwrap();
+    let start = html_page.find("<code>z2p_").unwrap() + "<code>".len();
+    let end = start + html_page[start..].find("</code>").unwrap();
+    let token = html_page[start..end].to_owned();
+    // It is not carried to the next page in a flash message cookie
+    assert!(cookies.iter().all(|c| !c.contains(&token)));
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("CI pipeline"));
+    assert!(html_page.contains("never"));
+    assert!(!html_page.contains(&token));
+    let stored = sqlx::query!("SELECT token_id, token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+
+    // Act - Part 2 - Use it
+    let response = app.get_api("/issues", &token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = app.get_api_tokens_html().await;
+    assert!(!html_page.contains("never"));
+
+    // Act - Part 3 - Revoke it
+    let response = app.post_revoke_api_token(&stored.token_id).await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("the token has been revoked"));
+    let response = app.get_api("/issues", &token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn issues_created_through_the_api_are_delivered_once() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_api(&app).await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI pipeline").await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "markdown": "Hi **{{ subscriber.name }}**" },
+        "lists": ["newsletter"],
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Create the issue
+    let response = app.post_api_issue(&token, &idempotency_key, &body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        location,
+        format!("/api/v1/issues/{}", issue["id"].as_str().unwrap())
+    );
+    assert_eq!(issue["title"], "Newsletter title");
+    assert_eq!(issue["status"], "published");
+
+    // Act - Part 2 - Retry it
+    let response = app.post_api_issue(&token, &idempotency_key, &body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let replayed: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(replayed, issue);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_issues(&app).await, 1);
+    assert_eq!(issue_recipients(&app).await, vec!["ursula@example.com"]);
+    let response = app.get_api(&location.replace("/api/v1", ""), &token).await;
+    let details: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(details["lists"], serde_json::json!(["newsletter"]));
+    assert_eq!(
+        details["content"]["html"],
+        "<p>Hi <strong>{{ subscriber.name }}</strong></p>\n"
+    );
+}
+
+#[tokio::test]
+async fn invalid_issues_are_rejected_with_a_json_error() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI pipeline").await;
+    let issue = |f: fn(&mut serde_json::Value)| {
+        let mut body = serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>Body</p>", "text": "Body" },
+            "lists": ["newsletter"],
+        });
+        f(&mut body);
+        body
+    };
+    let test_cases = vec![
+        (issue(|b| b["lists"] = serde_json::json!([])), "no list"),
+        (
+            issue(|b| b["lists"] = serde_json::json!(["newsletter", "gardening"])),
+            "an unknown list",
+        ),
+        (
+            issue(|b| b["scheduled_for"] = "2000-01-01T00:00:00Z".into()),
+            "a date in the past",
+        ),
+        (
+            issue(|b| b["content"]["text"] = "{{ subscriber.nmae }}".into()),
+            "an invalid template",
+        ),
+        (
+            issue(|b| b["segment_id"] = Uuid::new_v4().to_string().into()),
+            "an unknown segment",
+        ),
+        (issue(|b| b["content"] = "Body".into()), "malformed content"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app
+            .post_api_issue(&token, &Uuid::new_v4().to_string(), &body)
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "the API did not reject an issue with {description}"
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+    // The idempotency key is required
+    let response = app
+        .api_client
+        .post(format!("{}/api/v1/issues", &app.address))
+        .bearer_auth(&token)
+        .json(&issue(|_| {}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(n_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn issues_and_their_stats_can_be_retrieved() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_api(&app).await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("Dashboard").await;
+    let issue_id = publish_issue(&app, "Newsletter title", "<p>Body</p>", true).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act
+    let issues: serde_json::Value = app.get_api("/issues", &token).await.json().await.unwrap();
+    let issue = app.get_api(&format!("/issues/{issue_id}"), &token).await;
+    let stats = app
+        .get_api(&format!("/issues/{issue_id}/stats"), &token)
+        .await;
+
+    // Assert
+    assert_eq!(issues["issues"][0]["id"], issue_id.to_string());
+    assert_eq!(issues["issues"][0]["private"], true);
+    assert_eq!(issue.status().as_u16(), 200);
+    let issue: serde_json::Value = issue.json().await.unwrap();
+    assert_eq!(issue["content"]["html"], "<p>Body</p>");
+    assert!(issue["content"]["markdown"].is_null());
+    assert_eq!(stats.status().as_u16(), 200);
+    let stats: serde_json::Value = stats.json().await.unwrap();
+    assert_eq!(stats["deliveries"]["sent"], 1);
+    assert_eq!(stats["engagement"]["opens"], 0);
+    for path in ["", "/stats"] {
+        let response = app
+            .get_api(&format!("/issues/{}{path}", Uuid::new_v4()), &token)
+            .await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_a_page_at_a_time() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_api(&app).await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "octavia@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "nora@example.com", "newsletter", false).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CRM sync").await;
+
+    // Act
+    let first_page: serde_json::Value = app
+        .get_api("/subscribers?limit=2", &token)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let second_page: serde_json::Value = app
+        .get_api("/subscribers?limit=2&offset=2", &token)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let emails = |page: &serde_json::Value| -> Vec<String> {
+        page["subscribers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["email"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(
+        emails(&first_page),
+        vec!["ursula@example.com", "octavia@example.com"]
+    );
+    assert_eq!(
+        first_page["subscribers"][0]["lists"],
+        serde_json::json!(["newsletter"])
+    );
+    assert_eq!(emails(&second_page), vec!["nora@example.com"]);
+    assert_eq!(
+        second_page["subscribers"][0]["status"],
+        "pending_confirmation"
+    );
+    for query in ["limit=0", "limit=1001", "offset=-1", "limit=ten"] {
+        let response = app.get_api(&format!("/subscribers?{query}"), &token).await;
+        assert_eq!(response.status().as_u16(), 400, "{query} was accepted");
+    }
+}
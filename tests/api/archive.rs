@@ -0,0 +1,180 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, publish_issue, spawn_app, AcceptBatch,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::Mock;
+
+#[tokio::test]
+async fn only_public_published_issues_are_in_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let public_id = publish_issue(&app, "Public issue", "<p>Public body</p>", false).await;
+    let private_id = publish_issue(&app, "Private issue", "<p>Private body</p>", true).await;
+    app.post_create_draft(&serde_json::json!({
+        "title": "Draft issue",
+        "text_content": "Draft body as plain text",
+        "html_content": "<p>Draft body as HTML</p>",
+    }))
+    .await;
+    let draft_id = sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = 'Draft issue'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id;
+    app.post_logout().await;
+
+    // Act
+    let html_page = app.get_archive_html().await;
+
+    // Assert
+    assert!(html_page.contains(&format!(
+        r#"<a href="/archive/{public_id}">Public issue</a>"#
+    )));
+    assert!(!html_page.contains("Private issue"));
+    assert!(!html_page.contains("Draft issue"));
+    let response = app.get_archived_issue(&public_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<p>Public body</p>"));
+    for issue_id in [private_id, draft_id, Uuid::new_v4()] {
+        let response = app.get_archived_issue(&issue_id).await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[tokio::test]
+async fn archived_issues_are_rendered_safely() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(
+        &app,
+        "Newsletter title",
+        r#"<p onclick="steal()">Hi {{ subscriber.name }}</p><script>steal()</script>"#,
+        false,
+    )
+    .await;
+
+    // Act
+    let html_page = app
+        .get_archived_issue(&issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("<p>Hi reader</p>"));
+    assert!(!html_page.contains("steal()"));
+}
+
+#[tokio::test]
+async fn emails_link_to_the_web_version_of_public_issues_only() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let public_id = publish_issue(&app, "Public issue", "<p>Public body</p>", false).await;
+    app.dispatch_all_pending_emails().await;
+    publish_issue(&app, "Private issue", "<p>Private body</p>", true).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let batches: Vec<_> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.url.path() == "/email/batch")
+        .collect();
+    let archive_link = format!("{}/archive/{}", app.base_url, public_id);
+    let public_email: Vec<serde_json::Value> = serde_json::from_slice(&batches[0].body).unwrap();
+    assert!(public_email[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!(
+            r#"<a href="{archive_link}">View in your browser</a>"#
+        )));
+    assert!(public_email[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("View in your browser: {archive_link}")));
+    let private_email: Vec<serde_json::Value> = serde_json::from_slice(&batches[1].body).unwrap();
+    assert!(!private_email[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("View in your browser"));
+}
+
+#[tokio::test]
+async fn admins_can_keep_an_issue_private() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app, "Newsletter title", "<p>Body</p>", false).await;
+    let report_url = format!("/admin/newsletters/{issue_id}");
+
+    // Act - Part 1 - Make the issue private
+    let response = app.post_issue_visibility(&issue_id, "private").await;
+    assert_is_redirect_to(&response, &report_url);
+
+    // Assert
+    let report = app.get_newsletter_issue_report(&issue_id).await;
+    assert!(report
+        .text()
+        .await
+        .unwrap()
+        .contains("This issue is private: it is not in the public archive."));
+    let response = app.get_archived_issue(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    // Act - Part 2 - Make it public again
+    let response = app.post_issue_visibility(&issue_id, "public").await;
+    assert_is_redirect_to(&response, &report_url);
+
+    // Assert
+    let response = app.get_archived_issue(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn issues_that_do_not_render_are_shown_as_stored() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app, "Newsletter title", "<p>Body</p>", false).await;
+    // Issues published before they were templates were never validated
+    sqlx::query!(
+        "UPDATE newsletter_issues SET html_content = $1 WHERE newsletter_issue_id = $2",
+        "<p>Use {% raw %} to escape</p><script>steal()</script>",
+        issue_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_archived_issue(&issue_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p>Use {% raw %} to escape</p>"));
+    assert!(!html_page.contains("<script>"));
+}
@@ -0,0 +1,159 @@
+use crate::helpers::{publish_issue, spawn_app, TestApp};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+async fn get_feed(app: &TestApp, path: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = app.api_client.get(format!("{}{}", &app.address, path));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn feeds_list_the_latest_public_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let first_id = publish_issue(&app, "First issue", "<p>First body</p>", false).await;
+    let second_id = publish_issue(&app, "Second issue", "<p>Second body</p>", false).await;
+    publish_issue(&app, "Private issue", "<p>Private body</p>", true).await;
+    let permalink = |id| format!("{}/archive/{}", app.base_url, id);
+
+    // Act - Part 1 - RSS
+    let response = get_feed(&app, "/feed.rss", &[]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/rss+xml; charset=utf-8"
+    );
+    let channel = rss::Channel::read_from(&response.bytes().await.unwrap()[..]).unwrap();
+
+    // Assert
+    let items: Vec<_> = channel
+        .items()
+        .iter()
+        .map(|i| (i.title().unwrap(), i.link().unwrap().to_owned()))
+        .collect();
+    assert_eq!(
+        items,
+        vec![
+            ("Second issue", permalink(second_id)),
+            ("First issue", permalink(first_id)),
+        ]
+    );
+    assert_eq!(channel.items()[0].description(), Some("<p>Second body</p>"));
+    assert!(channel.items()[0].pub_date().is_some());
+
+    // Act - Part 2 - Atom
+    let response = get_feed(&app, "/feed.atom", &[]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let feed = atom_syndication::Feed::read_from(&response.bytes().await.unwrap()[..]).unwrap();
+
+    // Assert
+    let entries: Vec<_> = feed
+        .entries()
+        .iter()
+        .map(|e| (e.title().as_str(), e.links()[0].href().to_owned()))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![
+            ("Second issue", permalink(second_id)),
+            ("First issue", permalink(first_id)),
+        ]
+    );
+    assert_eq!(
+        feed.entries()[0].content().unwrap().value(),
+        Some("<p>Second body</p>")
+    );
+    assert_eq!(feed.updated(), feed.entries()[0].updated());
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_not_downloaded_again() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_issue(&app, "First issue", "<p>First body</p>", false).await;
+
+    for path in ["/feed.rss", "/feed.atom"] {
+        let response = get_feed(&app, path, &[]).await;
+        let etag = response.headers()[ETAG].to_str().unwrap().to_owned();
+        let last_modified = response.headers()[LAST_MODIFIED]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        // Act - Part 1 - The feed has not changed
+        let response = get_feed(&app, path, &[(IF_NONE_MATCH.as_str(), &etag)]).await;
+        assert_eq!(response.status().as_u16(), 304, "{path}");
+        assert_eq!(response.headers()[ETAG], etag.as_str());
+        assert!(response.bytes().await.unwrap().is_empty());
+        let response = get_feed(&app, path, &[(IF_MODIFIED_SINCE.as_str(), &last_modified)]).await;
+        assert_eq!(response.status().as_u16(), 304, "{path}");
+
+        // Act - Part 2 - An entity tag that does not match wins over the date
+        let response = get_feed(
+            &app,
+            path,
+            &[
+                (IF_NONE_MATCH.as_str(), "\"outdated\""),
+                (IF_MODIFIED_SINCE.as_str(), &last_modified),
+            ],
+        )
+        .await;
+        assert_eq!(response.status().as_u16(), 200, "{path}");
+    }
+
+    // Act - Part 3 - A new issue changes the feed
+    let response = get_feed(&app, "/feed.atom", &[]).await;
+    let etag = response.headers()[ETAG].to_str().unwrap().to_owned();
+    publish_issue(&app, "Second issue", "<p>Second body</p>", false).await;
+    let response = get_feed(&app, "/feed.atom", &[(IF_NONE_MATCH.as_str(), &etag)]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_ne!(response.headers()[ETAG], etag.as_str());
+}
+
+#[tokio::test]
+async fn issues_that_do_not_render_do_not_break_the_feeds() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let legacy_id = publish_issue(&app, "Legacy issue", "<p>Legacy body</p>", false).await;
+    publish_issue(&app, "Latest issue", "<p>Latest body</p>", false).await;
+    // Issues published before they were templates were never validated
+    sqlx::query!(
+        "UPDATE newsletter_issues SET html_content = $1 WHERE newsletter_issue_id = $2",
+        "<p>Use {% raw %} to escape</p>",
+        legacy_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let rss = get_feed(&app, "/feed.rss", &[]).await;
+    let atom = get_feed(&app, "/feed.atom", &[]).await;
+
+    // Assert
+    assert_eq!(rss.status().as_u16(), 200);
+    let channel = rss::Channel::read_from(&rss.bytes().await.unwrap()[..]).unwrap();
+    assert_eq!(channel.items().len(), 2);
+    assert_eq!(
+        channel.items()[1].description(),
+        Some("<p>Use {% raw %} to escape</p>")
+    );
+    assert_eq!(atom.status().as_u16(), 200);
+    let feed = atom_syndication::Feed::read_from(&atom.bytes().await.unwrap()[..]).unwrap();
+    assert_eq!(feed.entries().len(), 2);
+    assert_eq!(
+        feed.entries()[1].content().unwrap().value(),
+        Some("<p>Use {% raw %} to escape</p>")
+    );
+}
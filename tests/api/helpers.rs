@@ -1,12 +1,21 @@
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
 use zero2prod::email_client::EmailClient;
 use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::publish_due_issues;
+use zero2prod::routes::preferences_link;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -41,6 +50,9 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
     pub email_client: EmailClient,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub configuration: Settings,
 }
 
 impl TestApp {
@@ -75,10 +87,62 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
-    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+    /// Extract the unsubscribe link from a newsletter issue sent to a subscriber,
+    /// as the first email of a batch
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let batch: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let raw_link = linkify::LinkFinder::new()
+            .links(batch[0]["TextBody"].as_str().unwrap())
+            .map(|l| l.as_str().to_owned())
+            .find(|l| l.contains("/subscriptions/unsubscribe"))
+            .expect("no unsubscribe link in the email");
+        let mut unsubscribe_link = reqwest::Url::parse(&raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
+    /// Build the preferences link found in the newsletter issues sent to a subscriber
+    pub fn get_preferences_link(&self, subscriber_id: Uuid) -> reqwest::Url {
+        let raw_link = preferences_link(&self.base_url, subscriber_id, &self.hmac_secret);
+        let mut preferences_link = reqwest::Url::parse(&raw_link).unwrap();
+        preferences_link.set_port(Some(self.port)).unwrap();
+        preferences_link
+    }
+
+    pub async fn get_preferences_html(&self, preferences_link: &reqwest::Url) -> String {
+        self.api_client
+            .get(preferences_link.clone())
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_preferences(
+        &self,
+        preferences_link: &reqwest::Url,
+        body: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.api_client
+            .post(preferences_link.clone())
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_newsletters(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
         self.api_client
             .post(&format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
             .json(&body)
             .send()
             .await
@@ -176,20 +240,638 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn get_newsletter_issue_report(&self, issue_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters/{}", &self.address, issue_id))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_issue_visibility(
+        &self,
+        issue_id: &Uuid,
+        visibility: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/visibility",
+                &self.address, issue_id
+            ))
+            .form(&serde_json::json!({ "visibility": visibility }))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_archive_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/archive", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_archived_issue(&self, issue_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/archive/{}", &self.address, issue_id))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_drafts_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/drafts", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_draft<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters/drafts", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_draft(&self, draft_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{}",
+                &self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_draft_html(&self, draft_id: &Uuid) -> String {
+        self.get_draft(draft_id).await.text().await.unwrap()
+    }
+
+    pub async fn post_update_draft<Body>(&self, draft_id: &Uuid, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}",
+                &self.address, draft_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_draft_preview_html(&self, draft_id: &Uuid) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{}/preview",
+                &self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_send_test_email<Body>(
+        &self,
+        draft_id: &Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}/test",
+                &self.address, draft_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_publish_draft<Body>(&self, draft_id: &Uuid, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}/publish",
+                &self.address, draft_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_scheduled_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/scheduled", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_cancel_scheduled_issue(&self, issue_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/cancel",
+                &self.address, issue_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_reschedule_issue<Body>(
+        &self,
+        issue_id: &Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/reschedule",
+                &self.address, issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscribers_html(&self, query: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_subscriber_details(&self, subscriber_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscriber_details_html(&self, subscriber_id: &Uuid) -> String {
+        self.get_subscriber_details(subscriber_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_import_subscribers<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// `action` is one of `confirm`, `unsubscribe` or `delete`.
+    pub async fn post_subscriber_action(
+        &self,
+        subscriber_id: &Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_list<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Create a mailing list straight in the database
+    pub async fn insert_list(&self, name: &str, slug: &str) -> Uuid {
+        let list_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO lists (list_id, name, slug) VALUES ($1, $2, $3)",
+            list_id,
+            name,
+            slug,
+        )
+        .execute(&self.db_pool)
+        .await
+        .unwrap();
+        list_id
+    }
+
+    pub async fn post_subscriber_tags(
+        &self,
+        subscriber_id: &Uuid,
+        tags: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/tags",
+                &self.address, subscriber_id
+            ))
+            .form(&[("tags", tags)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_subscriber_attributes(
+        &self,
+        subscriber_id: &Uuid,
+        attributes: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/attributes",
+                &self.address, subscriber_id
+            ))
+            .form(&[("attributes", attributes)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_segments_html(&self, query: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/segments?{}", &self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_segment<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/segments", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/api_tokens", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_api_token(&self, name: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/api_tokens", &self.address))
+            .form(&serde_json::json!({ "name": name }))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_revoke_api_token(&self, token_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/api_tokens/{}/revoke",
+                &self.address, token_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Create an API token through the admin UI, as the logged in user.
+    pub async fn create_api_token(&self, name: &str) -> String {
+        let response = self.post_create_api_token(name).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let html_page = response.text().await.unwrap();
+        let start = html_page.find("<code>z2p_").unwrap() + "<code>".len();
+        let end = start + html_page[start..].find("</code>").unwrap();
+        html_page[start..end].to_owned()
+    }
+
+    pub async fn get_api(&self, path: &str, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/api/v1{}", &self.address, path))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_api_issue(
+        &self,
+        token: &str,
+        idempotency_key: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/api/v1/issues", &self.address))
+            .bearer_auth(token)
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_dead_letters(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_dead_letters_html(&self) -> String {
+        self.get_dead_letters().await.text().await.unwrap()
+    }
+
+    pub async fn post_requeue_dead_letter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/dead_letters/requeue", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Post a bounce notification, authenticated with the configured secret.
+    pub async fn post_bounce_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/email/bounce", &self.address))
+            .header(
+                "X-Webhook-Secret",
+                self.configuration
+                    .email_client
+                    .webhook_secret
+                    .expose_secret(),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
-            {
-                break;
+            match self.try_execute_task().await {
+                ExecutionOutcome::EmptyQueue => break,
+                ExecutionOutcome::QuotaExhausted { retry_after } => {
+                    tokio::time::sleep(retry_after).await
+                }
+                ExecutionOutcome::TaskCompleted => {}
             }
         }
     }
+
+    /// Run a single iteration of the delivery worker.
+    pub async fn try_execute_task(&self) -> ExecutionOutcome {
+        try_execute_task(
+            &self.db_pool,
+            &self.email_client,
+            &self.configuration.email_client.rate_limit.rate_limiter(),
+            &self.base_url,
+            &self.hmac_secret,
+            self.configuration.worker.track_opens_and_clicks,
+        )
+        .await
+        .unwrap()
+    }
+
+    pub async fn publish_due_issues(&self) -> usize {
+        publish_due_issues(&self.db_pool).await.unwrap()
+    }
+}
+
+/// Stand-in for Postmark's batch endpoint: every email of the batch is accepted.
+#[derive(Default)]
+pub struct AcceptBatch {
+    delay: Duration,
+}
+
+impl AcceptBatch {
+    pub fn with_delay(delay: Duration) -> Self {
+        Self { delay }
+    }
+}
+
+impl wiremock::Respond for AcceptBatch {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let emails: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = emails
+            .iter()
+            .map(|email| serde_json::json!({ "ErrorCode": 0, "Message": "OK", "To": email["To"] }))
+            .collect();
+        ResponseTemplate::new(200)
+            .set_body_json(results)
+            .set_delay(self.delay)
+    }
+}
+
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(&serde_json::json!({
+        "name": name,
+        "email": email,
+    }))
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Inspect the requests received by the mock Postmark server
+    // to retrive the confirmation link and return it
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Subscribe `email` to `list`, following the confirmation link
+/// if `confirm` is set. Returns the id of the subscriber.
+/// A mock of the email API must be mounted.
+pub async fn subscribe_to_list(app: &TestApp, email: &str, list: &str, confirm: bool) -> Uuid {
+    let body = serde_urlencoded::to_string([("name", "Reader"), ("email", email), ("list", list)])
+        .unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    if confirm {
+        let email_request = app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let confirmation_links = app.get_confirmation_links(&email_request);
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+/// Publish an issue through the admin form and return its identifier.
+pub async fn publish_issue(app: &TestApp, title: &str, html_content: &str, private: bool) -> Uuid {
+    let mut body = serde_json::json!({
+        "title": title,
+        "text_content": "Newsletter body as plain text",
+        "html_content": html_content,
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    });
+    if private {
+        body["private"] = "on".into();
+    }
+    let response = app.post_publish_newsletter(&body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        title
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+/// Recipients of the newsletter issues sent so far, sorted.
+pub async fn issue_recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/email/batch")
+        .flat_map(|r| serde_json::from_slice::<Vec<serde_json::Value>>(&r.body).unwrap())
+        .map(|email| email["To"].as_str().unwrap().to_owned())
+        .collect();
+    recipients.sort();
+    recipients
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application with some adjustments to the test configuration.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // Executed only the first time this is invoked.
     Lazy::force(&TRACING);
 
@@ -203,6 +885,7 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        configure(&mut c);
         c
     };
 
@@ -213,7 +896,7 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("failed to build application");
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    let _ = tokio::spawn(application.run_until_stopped(CancellationToken::new()));
 
     let client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -228,7 +911,10 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client: client,
-        email_client: configuration.email_client.client(),
+        email_client: configuration.email_client.clone().client(),
+        base_url: configuration.application.base_url.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        configuration,
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
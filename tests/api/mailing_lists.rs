@@ -0,0 +1,230 @@
+use crate::helpers::{
+    assert_is_redirect_to, issue_recipients, spawn_app, subscribe_to_list, AcceptBatch,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=gardening".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn a_confirmed_subscriber_joining_another_list_must_confirm_it() {
+    // Arrange
+    let app = spawn_app().await;
+    app.insert_list("Rust", "rust").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    let list_status = || async {
+        sqlx::query!(
+            r#"
+            SELECT ls.status
+            FROM list_subscriptions ls
+            JOIN lists l ON l.list_id = ls.list_id
+            WHERE l.slug = 'rust'
+            "#,
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+    };
+
+    // Act - Part 1 - Join the second list
+    subscribe_to_list(&app, "ursula@example.com", "rust", false).await;
+    assert_eq!(list_status().await, "pending_confirmation");
+
+    // Act - Part 2 - Follow the confirmation link
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(list_status().await, "confirmed");
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_the_confirmed_subscribers_of_their_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.insert_list("Rust", "rust").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .mount(&app.email_server)
+        .await;
+    subscribe_to_list(&app, "alice@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "carol@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "carol@example.com", "rust", true).await;
+    subscribe_to_list(&app, "bob@example.com", "rust", false).await;
+    app.test_user.login(&app).await;
+    let publish = |lists: &[&str]| {
+        let mut body = vec![
+            ("title", "Newsletter title".to_owned()),
+            ("text_content", "Newsletter body as plain text".to_owned()),
+            ("html_content", "<p>Newsletter body as HTML</p>".to_owned()),
+            ("idempotency_key", uuid::Uuid::new_v4().to_string()),
+        ];
+        body.extend(lists.iter().map(|l| ("list", l.to_string())));
+        body
+    };
+
+    // Act - Part 1 - Send to a single list
+    let response = app.post_publish_newsletter(&publish(&["rust"])).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(issue_recipients(&app).await, vec!["carol@example.com"]);
+
+    // Act - Part 2 - Send to both lists
+    let response = app
+        .post_publish_newsletter(&publish(&["newsletter", "rust"]))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Subscribers on both lists get the issue once
+    assert_eq!(
+        issue_recipients(&app).await,
+        vec![
+            "alice@example.com",
+            "carol@example.com",
+            "carol@example.com"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_who_leave_a_list_after_an_issue_is_queued_do_not_receive_it() {
+    // Arrange
+    let app = spawn_app().await;
+    app.insert_list("Rust", "rust").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .mount(&app.email_server)
+        .await;
+    subscribe_to_list(&app, "alice@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "alice@example.com", "rust", true).await;
+    subscribe_to_list(&app, "carol@example.com", "rust", true).await;
+    app.test_user.login(&app).await;
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "list": "rust",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Alice leaves the list the issue is sent to, not the other one
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'unsubscribed'
+        WHERE list_id = (SELECT list_id FROM lists WHERE slug = 'rust')
+            AND subscriber_id = (SELECT id FROM subscriptions WHERE email = 'alice@example.com')
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(issue_recipients(&app).await, vec!["carol@example.com"]);
+}
+
+#[tokio::test]
+async fn an_issue_must_be_sent_to_at_least_one_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("pick at least one list to send the issue to"));
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn admins_can_create_lists_with_a_unique_slug() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let list = serde_json::json!({ "name": "Rust", "slug": "rust" });
+
+    // Act - Part 1 - Create the list
+    let response = app.post_create_list(&list).await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("the list has been created"));
+    assert!(html_page.contains("<td>Rust</td><td>rust</td><td>0</td>"));
+
+    // Act - Part 2 - Create it again
+    let response = app.post_create_list(&list).await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("a list with the same name or slug already exists"));
+
+    // Act - Part 3 - Use an invalid slug
+    app.post_create_list(&serde_json::json!({ "name": "Go", "slug": "Go lang" }))
+        .await;
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("Go lang is not a valid slug"));
+}
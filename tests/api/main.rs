@@ -1,7 +1,18 @@
+mod admin_subscribers;
+mod api_v1;
+mod archive;
 mod change_password;
+mod feeds;
 mod health_check;
 mod helpers;
 mod login;
+mod mailing_lists;
 mod newsletter;
+mod newsletter_drafts;
+mod segments;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
@@ -1,55 +1,16 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    spawn_app_with, subscribe_to_list, AcceptBatch, TestApp,
+};
+use std::num::NonZeroU32;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use wiremock::matchers::{any, method, path};
 use wiremock::MockBuilder;
 use wiremock::{Mock, ResponseTemplate};
-
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
-        "name": name,
-        "email": email,
-    }))
-    .unwrap();
-
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
-
-    // Inspect the requests received by the mock Postmark server
-    // to retrive the confirmation link and return it
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-    app.get_confirmation_links(&email_request)
-}
-
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
+use zero2prod::idempotency::delete_expired_keys;
+use zero2prod::issue_delivery_worker::{run_worker_until_stopped, ExecutionOutcome};
+use zero2prod::routes::unsubscribe_link;
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -73,6 +34,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
         "text_content": "Newsletter body in plain text",
         "html_content": "<p>Newsletter body as HTML</p>",
         "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
     });
 
     let response = app.post_publish_newsletter(&newsletter_request_body).await;
@@ -92,9 +54,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(AcceptBatch::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -105,6 +67,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
         "text_content": "Newsletter body as plain text",
         "html_content": "<p>Newsletter body as HTML</p>",
         "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
     });
     let response = app.post_publish_newsletter(&newsletter_request_body).await;
     assert_is_redirect_to(&response, "/admin/newsletters");
@@ -149,7 +112,9 @@ async fn newsletters_returns_400_for_invalid_data() {
     ];
 
     for (invalid_body, error_message) in test_cases {
-        let response = app.post_newsletters(invalid_body).await;
+        let response = app
+            .post_newsletters(invalid_body, &uuid::Uuid::new_v4().to_string())
+            .await;
 
         // Ass
         assert_eq!(
@@ -161,6 +126,112 @@ async fn newsletters_returns_400_for_invalid_data() {
     }
 }
 
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .header("Idempotency-Key", uuid::Uuid::new_v4().to_string())
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn the_json_endpoint_queues_issues_idempotently() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act - Part 1 - The issue is accepted, not sent
+    let response = app.post_newsletters(body.clone(), &idempotency_key).await;
+    assert_eq!(202, response.status().as_u16());
+    let received_requests = app.email_server.received_requests().await.unwrap();
+    assert!(received_requests
+        .iter()
+        .all(|r| r.url.path() != "/email/batch"));
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let status_url = format!("/api/v1/issues/{issue_id}");
+    assert_eq!(response.headers()["Location"], status_url.as_str());
+    let accepted: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        accepted,
+        serde_json::json!({ "issue_id": issue_id, "status_url": status_url })
+    );
+
+    // Act - Part 2 - Retry
+    let response = app.post_newsletters(body.clone(), &idempotency_key).await;
+    assert_eq!(202, response.status().as_u16());
+    let replayed: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(accepted, replayed);
+
+    // Act - Part 3 - Without a key, every request publishes a new issue
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(202, response.status().as_u16());
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 2);
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    // Both issues are delivered in a single batch
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let batch: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(batch.len(), 2);
+}
+
 #[tokio::test]
 async fn newsletter_creation_is_idempotent() {
     // Arrange
@@ -168,9 +239,9 @@ async fn newsletter_creation_is_idempotent() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(AcceptBatch::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -183,6 +254,7 @@ async fn newsletter_creation_is_idempotent() {
         // We expect the idempotency key as part of the form data,
         // not as a header
         "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
     });
     let response = app.post_publish_newsletter(&newsletter_request_body).await;
     assert_is_redirect_to(&response, "/admin/newsletters");
@@ -202,6 +274,102 @@ async fn newsletter_creation_is_idempotent() {
     // Mock verifies on Drop that we have sent the newsletter email **once**
 }
 
+async fn expire_idempotency_keys(app: &TestApp, idempotency_keys: &[String]) {
+    sqlx::query!(
+        r#"
+        UPDATE idempotency SET created_at = now() - interval '49 hours'
+        WHERE idempotency_key = ANY($1)
+        "#,
+        idempotency_keys,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_are_processed_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": idempotency_key,
+        "list": "newsletter",
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Act - The key is past its retention window
+    expire_idempotency_keys(&app, &[idempotency_key]).await;
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 2);
+    // Mock verifies on Drop that the newsletter email has been sent twice
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_are_deleted_in_batches() {
+    // Arrange
+    let app =
+        spawn_app_with(|c| c.idempotency.cleanup_batch_size = NonZeroU32::new(2).unwrap()).await;
+    app.test_user.login(&app).await;
+    let idempotency_keys: Vec<String> = (0..5).map(|_| uuid::Uuid::new_v4().to_string()).collect();
+    for idempotency_key in &idempotency_keys {
+        app.post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key,
+            "list": "newsletter",
+        }))
+        .await;
+    }
+    expire_idempotency_keys(&app, &idempotency_keys[..3]).await;
+
+    // Act
+    let n_deleted_keys = delete_expired_keys(
+        &app.db_pool,
+        &app.configuration.idempotency,
+        &CancellationToken::new(),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(n_deleted_keys, 3);
+    let remaining =
+        sqlx::query!("SELECT idempotency_key FROM idempotency ORDER BY idempotency_key")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    let mut expected = idempotency_keys[3..].to_vec();
+    expected.sort();
+    let remaining: Vec<String> = remaining.into_iter().map(|r| r.idempotency_key).collect();
+    assert_eq!(remaining, expected);
+}
+
 #[tokio::test]
 async fn concurrent_form_submission_is_handled_gracefully() {
     // Arrange
@@ -209,11 +377,11 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         // Setting a long delay to ensure that the second request
         // arrives before the first one completes
-        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .respond_with(AcceptBatch::with_delay(Duration::from_millis(500)))
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -224,6 +392,7 @@ async fn concurrent_form_submission_is_handled_gracefully() {
         "text_content": "Newsletter body as plain text",
         "html_content": "<p>Newsletter body as HTML</p>",
         "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
     });
     let response1 = app.post_publish_newsletter(&newsletter_request_body);
     let response2 = app.post_publish_newsletter(&newsletter_request_body);
@@ -238,3 +407,906 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we have send the newsletter email **once**
 }
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    })
+}
+
+/// Dispatch pending emails until the queue is empty, making retried
+/// tasks immediately eligible for execution instead of waiting for their backoff.
+async fn dispatch_all_pending_emails_without_backoff(app: &TestApp) {
+    for _ in 0..20 {
+        app.dispatch_all_pending_emails().await;
+        let n_pending = sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&app.db_pool)
+            .await
+            .unwrap()
+            .rows_affected();
+        if n_pending == 0 {
+            return;
+        }
+    }
+    panic!("the delivery queue was not drained");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&newsletter_request_body())
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"is_delayed!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("the delivery task was dropped");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.is_delayed);
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2..)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    dispatch_all_pending_emails_without_backoff(&app).await;
+
+    // Assert
+    let dead_letter = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("the delivery task was not dead-lettered");
+    let html_page = app.get_dead_letters_html().await;
+    assert!(html_page.contains(&dead_letter.subscriber_email));
+}
+
+#[tokio::test]
+async fn dead_letters_can_be_requeued() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let failing_mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    dispatch_all_pending_emails_without_backoff(&app).await;
+    drop(failing_mock_guard);
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let dead_letter = sqlx::query!(
+        "SELECT newsletter_issue_id, subscriber_email FROM issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act - Part 1 - Requeue the failed delivery
+    let response = app
+        .post_requeue_dead_letter(&serde_json::json!({
+            "newsletter_issue_id": dead_letter.newsletter_issue_id,
+            "subscriber_email": dead_letter.subscriber_email,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dead_letters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_dead_letters_html().await;
+    assert!(html_page.contains("<p><i>the delivery has been requeued</i></p>"));
+    assert!(!html_page.contains(&dead_letter.subscriber_email));
+
+    // Act - Part 3 - Deliver the requeued issue
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that the requeued issue has been sent
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_dead_letters().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_delivery_report_counts_sent_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    let issue = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let html_page = app
+        .get_newsletter_issue_report(&issue.newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Pending: 1"));
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = app
+        .get_newsletter_issue_report(&issue.newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Sent: 1"));
+    assert!(html_page.contains("Pending: 0"));
+    // The published issue is linked from the newsletter form
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(&format!("/admin/newsletters/{}", issue.newsletter_issue_id)));
+}
+
+#[tokio::test]
+async fn the_delivery_report_lists_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    dispatch_all_pending_emails_without_backoff(&app).await;
+
+    // Assert
+    let delivery = sqlx::query!(
+        "SELECT newsletter_issue_id, subscriber_email, status FROM newsletter_issue_deliveries"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.status, "failed");
+    let html_page = app
+        .get_newsletter_issue_report(&delivery.newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Failed: 1"));
+    assert!(html_page.contains(&delivery.subscriber_email));
+}
+
+#[tokio::test]
+async fn the_delivery_report_of_an_unknown_issue_is_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_newsletter_issue_report(&uuid::Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+fn scheduled_newsletter_request_body(scheduled_for: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Scheduled newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
+        "scheduled_for": scheduled_for,
+    })
+}
+
+fn tomorrow() -> String {
+    (chrono::Utc::now() + chrono::Duration::days(1))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string()
+}
+
+/// Pretend the scheduled time of every issue has come.
+async fn make_scheduled_issues_due(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_for = now() - interval '1 minute' \
+        WHERE status = 'scheduled'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_before_their_time() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&scheduled_newsletter_request_body(&tomorrow()))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("the newsletter issue has been scheduled for"));
+    assert!(!html_page.contains("Scheduled newsletter title"));
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("Scheduled newsletter title"));
+    assert_eq!(app.publish_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&scheduled_newsletter_request_body(&tomorrow()))
+        .await;
+    make_scheduled_issues_due(&app).await;
+    assert_eq!(app.publish_due_issues().await, 1);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issue = sqlx::query!("SELECT status, published_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "published");
+    assert!(issue.published_at.is_some());
+    // A published issue is never enqueued twice
+    assert_eq!(app.publish_due_issues().await, 0);
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_that_fails_to_publish_does_not_hold_back_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut broken_issue = scheduled_newsletter_request_body(&tomorrow());
+    broken_issue["title"] = "Broken newsletter title".into();
+    app.post_publish_newsletter(&broken_issue).await;
+    app.post_publish_newsletter(&scheduled_newsletter_request_body(&tomorrow()))
+        .await;
+    // A segment whose filter no longer parses
+    let segment_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO segments (segment_id, name, filter, created_at) \
+        VALUES ($1, 'Broken', 'list = (', now())",
+        segment_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE newsletter_issues SET segment_id = $1 WHERE title = 'Broken newsletter title'",
+        segment_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    make_scheduled_issues_due(&app).await;
+    let n_published = app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_published, 1);
+    let issues = sqlx::query!("SELECT title, status FROM newsletter_issues ORDER BY title")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues[0].title, "Broken newsletter title");
+    assert_eq!(issues[0].status, "scheduled");
+    assert_eq!(issues[1].status, "published");
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_publish_newsletter(&scheduled_newsletter_request_body(&tomorrow()))
+        .await;
+    let issue = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_cancel_scheduled_issue(&issue.newsletter_issue_id)
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    // Assert
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("<p><i>the scheduled issue has been cancelled</i></p>"));
+    assert!(!html_page.contains("Scheduled newsletter title"));
+    make_scheduled_issues_due(&app).await;
+    assert_eq!(app.publish_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_publish_newsletter(&scheduled_newsletter_request_body(&tomorrow()))
+        .await;
+    let issue = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let next_week = (chrono::Utc::now() + chrono::Duration::days(7))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string();
+
+    // Act
+    let response = app
+        .post_reschedule_issue(
+            &issue.newsletter_issue_id,
+            &serde_json::json!({ "scheduled_for": next_week }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    // Assert
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("<p><i>the issue has been rescheduled</i></p>"));
+    assert!(html_page.contains(&next_week));
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&scheduled_newsletter_request_body("2020-01-01T10:00"))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>the scheduled date must be in the future</i></p>"));
+    let n_issues = sqlx::query!("SELECT COUNT(*) AS n FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, Some(0));
+}
+
+/// Stand-in for Postmark's batch endpoint, rejecting the first email of every batch.
+struct RejectFirstEmail;
+
+impl wiremock::Respond for RejectFirstEmail {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let emails: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = (0..emails.len())
+            .map(|i| match i {
+                0 => serde_json::json!({ "ErrorCode": 406, "Message": "Inactive recipient" }),
+                _ => serde_json::json!({ "ErrorCode": 0, "Message": "OK" }),
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
+#[tokio::test]
+async fn issues_are_delivered_in_a_single_batch() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let batch: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(batch.len(), 3);
+    // Every recipient gets their own unsubscribe link
+    let links: std::collections::HashSet<_> = batch
+        .iter()
+        .map(|email| email["Headers"][0]["Value"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(links.len(), 3);
+    // Mock verifies on Drop that all emails went through a single request
+}
+
+#[tokio::test]
+async fn only_the_failed_recipients_of_a_batch_are_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(RejectFirstEmail)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!("SELECT subscriber_email, n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+    let delivery = sqlx::query!("SELECT subscriber_email, status FROM newsletter_issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_ne!(delivery.subscriber_email, task.subscriber_email);
+}
+
+/// Run the delivery worker in the background, as `main` does.
+fn spawn_worker(
+    app: &TestApp,
+    n_workers: usize,
+    poll_interval: Duration,
+) -> (
+    tokio::task::JoinHandle<Result<(), anyhow::Error>>,
+    CancellationToken,
+) {
+    let mut configuration = app.configuration.clone();
+    configuration.worker.n_workers = n_workers;
+    configuration.worker.poll_interval_milliseconds = poll_interval.as_millis() as u64;
+    let shutdown = CancellationToken::new();
+    let worker = tokio::spawn(run_worker_until_stopped(configuration, shutdown.clone()));
+    (worker, shutdown)
+}
+
+async fn delivery_queue_length(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n
+}
+
+async fn wait_for_empty_delivery_queue(app: &TestApp, timeout: Duration) {
+    tokio::time::timeout(timeout, async {
+        while delivery_queue_length(app).await > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the deliveries were not processed in time");
+}
+
+#[tokio::test]
+async fn concurrent_workers_deliver_each_email_once() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+
+    // Act
+    let (worker, shutdown) = spawn_worker(&app, 4, Duration::from_millis(50));
+    wait_for_empty_delivery_queue(&app, Duration::from_secs(10)).await;
+    shutdown.cancel();
+
+    // Assert
+    let outcome = tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("the worker did not stop after shutdown was requested");
+    assert!(outcome.unwrap().is_ok());
+    // Mock verifies on Drop that the batch has been sent exactly once
+}
+
+#[tokio::test]
+async fn shutdown_lets_in_flight_deliveries_complete() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::with_delay(Duration::from_millis(600)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+
+    // Act - ask the worker to stop while the batch is being sent
+    let (worker, shutdown) = spawn_worker(&app, 1, Duration::from_millis(50));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    shutdown.cancel();
+    let outcome = tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("the worker did not stop after shutdown was requested");
+
+    // Assert
+    assert!(outcome.unwrap().is_ok());
+    assert_eq!(delivery_queue_length(&app).await, 0);
+    let delivery = sqlx::query!("SELECT status FROM newsletter_issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+}
+
+#[tokio::test]
+async fn idle_workers_are_woken_up_by_new_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // The workers find an empty queue and would not poll it again for a minute
+    let (_worker, shutdown) = spawn_worker(&app, 2, Duration::from_secs(60));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Act
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+
+    // Assert
+    wait_for_empty_delivery_queue(&app, Duration::from_secs(5)).await;
+    shutdown.cancel();
+}
+
+#[tokio::test]
+async fn deliveries_beyond_the_sending_quota_are_held_back() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.rate_limit.per_day = NonZeroU32::new(2)).await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+
+    // Act
+    let outcome = app.try_execute_task().await;
+
+    // Assert
+    assert!(matches!(outcome, ExecutionOutcome::QuotaExhausted { .. }));
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let batch: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(batch.len(), 2);
+    assert_eq!(delivery_queue_length(&app).await, 1);
+    // The held back delivery is neither retried nor reported as failed
+    let n_retries = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n_retries;
+    assert_eq!(n_retries, 0);
+}
+
+#[tokio::test]
+async fn the_dashboard_shows_the_sending_quota_usage() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.rate_limit.per_day = NonZeroU32::new(100)).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Nothing sent yet
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<li>per day: 0 of 100 emails</li>"));
+
+    // Act - Part 2 - Deliver an issue
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<li>per day: 2 of 100 emails</li>"));
+}
+
+#[tokio::test]
+async fn issues_are_rendered_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let subscriber_id = subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Issue #1",
+            "text_content": "Hi {{ subscriber.name }}! Leave: {{ unsubscribe_url }}",
+            "html_content": "<p>{{ issue.title }} for {{ subscriber.email }}</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "list": "newsletter",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let batch = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let emails: Vec<serde_json::Value> = serde_json::from_slice(&batch.body).unwrap();
+    let unsubscribe_link = unsubscribe_link(&app.base_url, subscriber_id, &app.hmac_secret);
+    assert!(emails[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("Hi Reader! Leave: {unsubscribe_link}")));
+    assert!(emails[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Issue #1 for ursula@example.com</p>"));
+}
+
+#[tokio::test]
+async fn invalid_templates_are_rejected_before_anything_is_queued() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for (html_content, error) in [
+        ("<p>Hi {{ subscriber.name</p>", "syntax error"),
+        ("<p>Hi {{ subscriber.nmae }}</p>", "undefined value"),
+    ] {
+        // Act
+        let response = app
+            .post_publish_newsletter(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": html_content,
+                "idempotency_key": uuid::Uuid::new_v4().to_string(),
+                "list": "newsletter",
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        assert!(response.text().await.unwrap().contains(error));
+    }
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn issues_written_in_markdown_are_sent_as_html_and_plain_text() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let subscriber_id = subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    app.test_user.login(&app).await;
+    let markdown_content = "Hi **{{ subscriber.name }}**!\n\n<script>alert(1)</script>\n\n\
+        [Unsubscribe]({{unsubscribe_url}})";
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "markdown_content": markdown_content,
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "list": "newsletter",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let batch = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let emails: Vec<serde_json::Value> = serde_json::from_slice(&batch.body).unwrap();
+    let unsubscribe_link = unsubscribe_link(&app.base_url, subscriber_id, &app.hmac_secret);
+    let html_body = emails[0]["HtmlBody"].as_str().unwrap();
+    assert!(html_body.starts_with("<p>Hi <strong>Reader</strong>!</p>"));
+    assert!(html_body.contains("&lt;script&gt;"));
+    assert!(html_body.contains(&format!(r#"<a href="{unsubscribe_link}">Unsubscribe</a>"#)));
+    let text_body = emails[0]["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with(&format!(
+        "Hi Reader!\n\n<script>alert(1)</script>\n\nUnsubscribe ({unsubscribe_link})"
+    )));
+    // The source is kept to edit the issue later
+    let saved = sqlx::query!("SELECT markdown_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.markdown_content.as_deref(), Some(markdown_content));
+}
@@ -0,0 +1,295 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, AcceptBatch, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn draft_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Draft title",
+        "text_content": "Draft body as plain text",
+        "html_content": "<p>Draft body as HTML</p>",
+    })
+}
+
+/// Save a draft through the admin form and return its identifier.
+async fn create_draft(app: &TestApp) -> Uuid {
+    let response = app.post_create_draft(&draft_request_body()).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .strip_prefix("/admin/newsletters/drafts/")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_drafts() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/newsletters/drafts", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let draft_id = create_draft(&app).await;
+
+    // Assert
+    let html_page = app.get_draft_html(&draft_id).await;
+    assert!(html_page.contains("<p><i>the draft has been saved</i></p>"));
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("Draft title"));
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(!html_page.contains("Draft title"));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_id = create_draft(&app).await;
+
+    // Act
+    let response = app
+        .post_update_draft(
+            &draft_id,
+            &serde_json::json!({
+                "title": "Updated title",
+                "text_content": "Updated body",
+                "html_content": "<p>Updated <b>body</b></p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/drafts/{draft_id}"));
+
+    // Assert
+    let html_page = app.get_draft_html(&draft_id).await;
+    assert!(html_page.contains("Updated title"));
+    // The HTML content is escaped inside the editor...
+    assert!(html_page.contains("&lt;p&gt;Updated &lt;b&gt;body&lt;/b&gt;&lt;/p&gt;"));
+    // ...but rendered in the preview
+    let html_page = app.get_draft_preview_html(&draft_id).await;
+    assert!(html_page.contains("<p>Updated <b>body</b></p>"));
+    assert!(html_page.contains("Updated body"));
+}
+
+#[tokio::test]
+async fn an_unknown_draft_is_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_draft(&Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_test_email_is_sent_to_the_given_address_only() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_send_test_email(
+            &draft_id,
+            &serde_json::json!({ "email": "editor@example.com" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/drafts/{draft_id}"));
+
+    // Assert
+    let html_page = app.get_draft_html(&draft_id).await;
+    assert!(html_page.contains("a test email has been sent to editor@example.com"));
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    // The first request is the confirmation email of the subscriber
+    let email_request = email_requests.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "editor@example.com");
+    assert_eq!(body["Subject"], "[Test] Draft title");
+    // Nothing has been enqueued for the subscribers
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_test_email_to_an_invalid_address_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_send_test_email(&draft_id, &serde_json::json!({ "email": "not-an-email" }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/drafts/{draft_id}"));
+    let html_page = app.get_draft_html(&draft_id).await;
+    assert!(html_page.contains("not a valid subscriber email"));
+}
+
+#[tokio::test]
+async fn publishing_a_draft_delivers_it_to_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = serde_json::json!({
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "list": "newsletter",
+        "scheduled_for": "",
+    });
+    let response = app.post_publish_draft(&draft_id, &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    // Submitting the form twice does not send the issue twice
+    let response = app.post_publish_draft(&draft_id, &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>the newsletter issue has been accepted</i></p>"));
+    assert!(html_page.contains("Draft title"));
+    // A published draft can no longer be edited
+    assert_eq!(app.get_draft(&draft_id).await.status().as_u16(), 404);
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn a_draft_with_an_invalid_template_cannot_be_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let draft_id = create_draft(&app).await;
+    app.post_update_draft(
+        &draft_id,
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "Draft body as plain text",
+            "html_content": "<p>Hi {{ subscriber.nmae }}</p>",
+        }),
+    )
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let publish_form = serde_json::json!({
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "list": "newsletter",
+        "scheduled_for": "",
+    });
+    let response = app.post_publish_draft(&draft_id, &publish_form).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    // The draft is kept, so that it can be fixed
+    assert_eq!(app.get_draft(&draft_id).await.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    // The idempotency key is released, so that the fixed draft can be
+    // published with it
+    app.post_update_draft(
+        &draft_id,
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "Draft body as plain text",
+            "html_content": "<p>Hi {{ subscriber.name }}</p>",
+        }),
+    )
+    .await;
+    let response = app.post_publish_draft(&draft_id, &publish_form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(app.get_draft(&draft_id).await.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn drafts_can_be_written_in_markdown() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_id = create_draft(&app).await;
+
+    // Act
+    app.post_update_draft(
+        &draft_id,
+        &serde_json::json!({
+            "title": "Draft title",
+            "markdown_content": "# Draft title\n\n- *one*\n- two",
+        }),
+    )
+    .await;
+
+    // Assert
+    // The source is shown in the editor...
+    let html_page = app.get_draft_html(&draft_id).await;
+    assert!(html_page.contains("# Draft title\n\n- *one*\n- two</textarea>"));
+    // ...and both contents are rendered from it
+    let html_page = app.get_draft_preview_html(&draft_id).await;
+    assert!(html_page.contains("<h1>Draft title</h1>\n<ul>\n<li><em>one</em></li>"));
+    assert!(html_page.contains("Draft title\n===========\n\n- one\n- two"));
+}
@@ -0,0 +1,182 @@
+use crate::helpers::{
+    assert_is_redirect_to, issue_recipients, spawn_app, subscribe_to_list, AcceptBatch, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn mount_email_api(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn segment_id(app: &TestApp, name: &str) -> Uuid {
+    sqlx::query!("SELECT segment_id FROM segments WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .segment_id
+}
+
+#[tokio::test]
+async fn admins_can_set_the_tags_and_attributes_of_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_api(&app).await;
+    let subscriber_id = subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    app.test_user.login(&app).await;
+    let details_url = format!("/admin/subscribers/{subscriber_id}");
+
+    // Act - Part 1 - Save tags and attributes
+    let response = app
+        .post_subscriber_tags(&subscriber_id, "beta, early-adopter, beta")
+        .await;
+    assert_is_redirect_to(&response, &details_url);
+    let response = app
+        .post_subscriber_attributes(&subscriber_id, r#"{"plan": "pro", "seats": 3}"#)
+        .await;
+    assert_is_redirect_to(&response, &details_url);
+
+    // Assert
+    let html_page = app.get_subscriber_details_html(&subscriber_id).await;
+    assert!(html_page.contains("the attributes have been saved"));
+    assert!(html_page.contains("&quot;plan&quot;: &quot;pro&quot;"));
+    let tags = sqlx::query!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let tags: Vec<&str> = tags.iter().map(|t| t.tag.as_str()).collect();
+    assert_eq!(tags, vec!["beta", "early-adopter"]);
+
+    // Act - Part 2 - Attributes must be a JSON object
+    app.post_subscriber_attributes(&subscriber_id, r#"["pro"]"#)
+        .await;
+    let html_page = app.get_subscriber_details_html(&subscriber_id).await;
+    assert!(html_page.contains("the attributes are not a JSON object"));
+    assert!(html_page.contains("&quot;seats&quot;: 3"));
+}
+
+#[tokio::test]
+async fn segments_are_previewed_and_validated_before_being_saved() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_api(&app).await;
+    let ursula = subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "octavia@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "nora@example.com", "newsletter", false).await;
+    app.test_user.login(&app).await;
+    app.post_subscriber_tags(&ursula, "beta").await;
+
+    // Act - Part 1 - Preview
+    let html_page = app
+        .get_segments_html("name=Beta&filter=tag+%3D+%22beta%22")
+        .await;
+    assert!(html_page.contains("1 confirmed subscriber(s) match this filter."));
+    let html_page = app
+        .get_segments_html("name=Recent&filter=subscribed_days_ago+%3C%3D+30")
+        .await;
+    assert!(html_page.contains("2 confirmed subscriber(s) match this filter."));
+
+    // Act - Part 2 - An invalid filter is not saved
+    let response = app
+        .post_create_segment(&serde_json::json!({ "name": "Beta", "filter": "tag == beta" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/segments");
+    let html_page = app.get_segments_html("").await;
+    assert!(html_page.contains("unexpected `=` at position 4"));
+
+    // Act - Part 3 - Save the segment, twice
+    let segment = serde_json::json!({ "name": "Beta", "filter": r#"tag = "beta""# });
+    app.post_create_segment(&segment).await;
+    let html_page = app.get_segments_html("").await;
+    assert!(html_page.contains("the segment has been created"));
+    assert!(
+        html_page.contains("<td>Beta</td><td><code>tag = &quot;beta&quot;</code></td><td>1</td>")
+    );
+    app.post_create_segment(&segment).await;
+    let html_page = app.get_segments_html("").await;
+    assert!(html_page.contains("a segment with the same name already exists"));
+
+    // Assert - The count is shown before publishing
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("Beta (1 matching)"));
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_only_reach_its_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_api(&app).await;
+    let ursula = subscribe_to_list(&app, "ursula@example.com", "newsletter", true).await;
+    let octavia = subscribe_to_list(&app, "octavia@example.com", "newsletter", true).await;
+    subscribe_to_list(&app, "nora@example.com", "newsletter", true).await;
+    app.test_user.login(&app).await;
+    app.post_subscriber_tags(&ursula, "beta").await;
+    app.post_subscriber_attributes(&octavia, r#"{"plan": "pro"}"#)
+        .await;
+    app.post_create_segment(&serde_json::json!({
+        "name": "Beta testers and pros",
+        "filter": r#"tag = "beta" or attributes.plan = "pro""#,
+    }))
+    .await;
+    let segment_id = segment_id(&app, "Beta testers and pros").await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "list": "newsletter",
+            "segment": segment_id.to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        issue_recipients(&app).await,
+        vec!["octavia@example.com", "ursula@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn an_unknown_segment_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "list": "newsletter",
+            "segment": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+}
@@ -1,6 +1,7 @@
 use crate::helpers::spawn_app;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::subscription_cleanup::{delete_stale_subscriptions, CleanupOutcome};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -157,3 +158,313 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_response = app.post_subscriptions(body.into()).await;
+    let second_response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+    let n_subscribers = sqlx::query!("SELECT COUNT(*) AS \"n!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_does_not_send_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_asks_for_a_new_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Subscribe again
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+
+    // Act - Part 2 - Follow the new confirmation link
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn a_confirmation_link_does_not_resubscribe_somebody_who_left() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn subscribing_with_a_suppressed_address_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    sqlx::query!(
+        "INSERT INTO email_suppressions (email, reason, suppressed_at) \
+        VALUES ('ursula_le_guin@gmail.com', 'hard_bounce', now())"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let n_subscribers = sqlx::query!("SELECT COUNT(*) AS \"n!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn cleanup_expires_stale_tokens_and_deletes_abandoned_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=tom&email=tom%40example.com".into())
+        .await;
+    // Ursula signed up long ago and never confirmed
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET subscribed_at = now() - interval '60 days'
+        WHERE email = 'ursula_le_guin@gmail.com'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET created_at = now() - interval '60 days'
+        WHERE subscriber_id = (
+            SELECT id FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com'
+        )
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = delete_stale_subscriptions(&app.db_pool, &app.configuration.subscriptions)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        outcome,
+        CleanupOutcome {
+            n_expired_tokens: 1,
+            n_deleted_tokens: 0,
+            n_abandoned_subscribers: 1,
+        }
+    );
+    let remaining = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].email, "tom@example.com");
+    // The tokens of abandoned subscribers are deleted with them
+    let tokens = sqlx::query!("SELECT expired_at FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert!(tokens[0].expired_at.is_none());
+}
+
+#[tokio::test]
+async fn cleanup_deletes_tokens_expired_for_longer_than_the_retention_period() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    // The first link expired long ago, the second one just now
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET created_at = now() - interval '200 days',
+            expired_at = now() - interval '150 days'
+        WHERE created_at = (SELECT MIN(created_at) FROM subscription_tokens)
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET created_at = now() - interval '60 days'
+        WHERE expired_at IS NULL
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = delete_stale_subscriptions(&app.db_pool, &app.configuration.subscriptions)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        outcome,
+        CleanupOutcome {
+            n_expired_tokens: 1,
+            n_deleted_tokens: 1,
+            n_abandoned_subscribers: 0,
+        }
+    );
+    let tokens = sqlx::query!("SELECT expired_at FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert!(tokens[0].expired_at.is_some());
+}
@@ -0,0 +1,305 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::{PreferencesToken, UnsubscribeToken};
+
+async fn insert_confirmed_subscriber(app: &TestApp, email: &str, name: &str) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, now(), 'confirmed')
+        "#,
+        subscriber_id,
+        email,
+        name,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+async fn join_default_list(app: &TestApp, subscriber_id: Uuid) {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (subscriber_id, list_id, status)
+        SELECT $1, list_id, 'confirmed' FROM lists WHERE slug = 'newsletter'
+        "#,
+        subscriber_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+fn redirect_target(preferences_link: &reqwest::Url) -> String {
+    format!(
+        "{}?{}",
+        preferences_link.path(),
+        preferences_link.query().unwrap()
+    )
+}
+
+#[tokio::test]
+async fn preferences_links_with_an_invalid_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+    let test_cases = vec![
+        ("not-a-token".to_owned(), "a malformed token"),
+        (
+            PreferencesToken::new(subscriber_id, yesterday, &app.hmac_secret)
+                .as_ref()
+                .to_owned(),
+            "an expired token",
+        ),
+        (
+            UnsubscribeToken::new(subscriber_id, &app.hmac_secret)
+                .as_ref()
+                .to_owned(),
+            "an unsubscribe token",
+        ),
+    ];
+
+    for (token, description) in test_cases {
+        let mut preferences_link = app.get_preferences_link(subscriber_id);
+        preferences_link.set_query(Some(&format!("token={}", urlencoding::encode(&token))));
+
+        // Act
+        let get = app
+            .api_client
+            .get(preferences_link.clone())
+            .send()
+            .await
+            .unwrap();
+        let post = app
+            .post_preferences(
+                &preferences_link,
+                &[("name", "x"), ("email", "x@example.com")],
+            )
+            .await;
+
+        // Assert
+        assert_eq!(get.status().as_u16(), 401, "{description} was accepted");
+        assert_eq!(post.status().as_u16(), 401, "{description} was accepted");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name_and_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    join_default_list(&app, subscriber_id).await;
+    app.insert_list("Rust", "rust").await;
+    app.insert_list("Go", "go").await;
+    let preferences_link = app.get_preferences_link(subscriber_id);
+
+    // Act - Part 1 - Save the preferences
+    let response = app
+        .post_preferences(
+            &preferences_link,
+            &[
+                ("name", "Ursula K. Le Guin"),
+                ("email", "ursula@example.com"),
+                ("list", "rust"),
+            ],
+        )
+        .await;
+    assert_is_redirect_to(&response, &redirect_target(&preferences_link));
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_preferences_html(&preferences_link).await;
+    assert!(html_page.contains("your preferences have been saved"));
+    assert!(html_page.contains(r#"value="rust" checked> Rust"#));
+    assert!(html_page.contains(r#"value="go"> Go"#));
+    assert!(html_page.contains(r#"value="newsletter"> Newsletter"#));
+
+    // Assert
+    let saved = sqlx::query!(
+        "SELECT name, email FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    assert_eq!(saved.email, "ursula@example.com");
+    let lists = sqlx::query!(
+        r#"
+        SELECT l.slug, ls.status
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let lists: Vec<(&str, &str)> = lists
+        .iter()
+        .map(|l| (l.slug.as_str(), l.status.as_str()))
+        .collect();
+    assert_eq!(
+        lists,
+        vec![("newsletter", "unsubscribed"), ("rust", "confirmed")]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe_from_the_preferences_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    let preferences_link = app.get_preferences_link(subscriber_id);
+    let html_page = app.get_preferences_html(&preferences_link).await;
+    let (_, action) = html_page
+        .split_once(r#"<form action=""#)
+        .and_then(|(_, rest)| rest.split_once(r#"<form action=""#))
+        .expect("no unsubscribe form on the preferences page");
+    let (action, _) = action.split_once('"').unwrap();
+    let action = htmlescape::decode_html(action).unwrap();
+    assert!(action.starts_with("/subscriptions/unsubscribe?"));
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}{}", app.address, action))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_invalid_name_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    let preferences_link = app.get_preferences_link(subscriber_id);
+
+    // Act
+    let response = app
+        .post_preferences(
+            &preferences_link,
+            &[("name", "Ursula{}"), ("email", "ursula@example.com")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &redirect_target(&preferences_link));
+    let html_page = app.get_preferences_html(&preferences_link).await;
+    assert!(html_page.contains("is not a valid subscriber name"));
+    let saved = sqlx::query!(
+        "SELECT name FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "Ursula");
+}
+
+#[tokio::test]
+async fn a_new_email_is_only_used_once_it_is_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    let preferences_link = app.get_preferences_link(subscriber_id);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Ask for the change
+    let response = app
+        .post_preferences(
+            &preferences_link,
+            &[("name", "Ursula"), ("email", "le.guin@example.com")],
+        )
+        .await;
+    assert_is_redirect_to(&response, &redirect_target(&preferences_link));
+    let html_page = app.get_preferences_html(&preferences_link).await;
+    assert!(html_page.contains("we sent a confirmation link to le.guin@example.com"));
+    let current_email = || async {
+        sqlx::query!(
+            "SELECT email, status FROM subscriptions WHERE id = $1",
+            subscriber_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+    };
+    assert_eq!(current_email().await.email, "ursula@example.com");
+
+    // Act - Part 2 - Follow the link sent to the new address
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "le.guin@example.com");
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = current_email().await;
+    assert_eq!(saved.email, "le.guin@example.com");
+    assert_eq!(saved.status, "confirmed");
+    // The link can't be used twice
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_email_change_is_rejected_if_the_address_is_taken_in_the_meantime() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    let preferences_link = app.get_preferences_link(subscriber_id);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_preferences(
+        &preferences_link,
+        &[("name", "Ursula"), ("email", "le.guin@example.com")],
+    )
+    .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    insert_confirmed_subscriber(&app, "le.guin@example.com", "Le Guin").await;
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.email, "ursula@example.com");
+}
@@ -0,0 +1,173 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, AcceptBatch, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Publish an issue to a single confirmed subscriber and return the
+/// request received by the email server.
+async fn deliver_newsletter_issue(app: &TestApp) -> wiremock::Request {
+    create_confirmed_subscriber(app).await;
+    app.test_user.login(app).await;
+
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn newsletter_issues_carry_unsubscribe_and_preferences_links_and_headers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let email_request = deliver_newsletter_issue(&app).await;
+
+    // Assert
+    let batch: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let body = &batch[0];
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/unsubscribe?token="));
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/preferences?token="));
+    let headers = body["Headers"].as_array().unwrap();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|h| h["Name"] == name)
+            .map(|h| h["Value"].as_str().unwrap().to_owned())
+            .unwrap()
+    };
+    assert!(header("List-Unsubscribe").contains(unsubscribe_link.path()));
+    assert_eq!(
+        header("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_asks_for_a_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = deliver_newsletter_issue(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"<form action="/subscriptions/unsubscribe?token="#));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = deliver_newsletter_issue(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = deliver_newsletter_issue(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Another newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn unsubscribe_links_with_an_invalid_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!(
+        "{}/subscriptions/unsubscribe?token={}.{}",
+        app.address,
+        uuid::Uuid::new_v4().simple(),
+        "forged-signature"
+    );
+
+    // Act
+    let get_response = reqwest::get(&url).await.unwrap();
+    let post_response = reqwest::Client::new().post(&url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 401);
+    assert_eq!(post_response.status().as_u16(), 401);
+}
@@ -0,0 +1,134 @@
+use crate::helpers::{
+    create_confirmed_subscriber, spawn_app, spawn_app_with, AcceptBatch, TestApp,
+};
+use wiremock::matchers::{method, path};
+use wiremock::Mock;
+
+/// Publish an issue with a single link and deliver it to every subscriber.
+/// Returns the HTML body of the email that has been sent.
+async fn deliver_issue_with_a_link(app: &TestApp) -> String {
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(AcceptBatch::default())
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(app).await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Read https://example.com/article",
+        "html_content": r#"<p>Read <a href="https://example.com/article">this</a></p>"#,
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let batch: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    batch[0]["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+/// Find the tracking link of the given kind (`o` or `c`) in `html`.
+fn tracking_link(app: &TestApp, html: &str, kind: &str) -> reqwest::Url {
+    let raw_link = linkify::LinkFinder::new()
+        .links(html)
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains(&format!("/t/{kind}/")))
+        .expect("no tracking link in the email");
+    let mut link = reqwest::Url::parse(&raw_link).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_tracked() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue_with_a_link(&app).await;
+    assert!(!html.contains(r#"href="https://example.com/article""#));
+
+    // Act - Part 1 - Load the tracking pixel
+    let response = app
+        .api_client
+        .get(tracking_link(&app, &html, "o"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+
+    // Act - Part 2 - Follow the link
+    let response = app
+        .api_client
+        .get(tracking_link(&app, &html, "c"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/article"
+    );
+
+    // Assert
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let html_page = app
+        .get_newsletter_issue_report(&issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<li>Open rate: 100.0% (1 opened)</li>"));
+    assert!(html_page.contains("<li>Click rate: 100.0% (1 clicked)</li>"));
+}
+
+#[tokio::test]
+async fn tracking_can_be_turned_off() {
+    // Arrange
+    let app = spawn_app_with(|c| c.worker.track_opens_and_clicks = false).await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let html = deliver_issue_with_a_link(&app).await;
+
+    // Assert
+    assert!(html.contains(r#"href="https://example.com/article""#));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn tampered_tracking_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue_with_a_link(&app).await;
+    let mut link = tracking_link(&app, &html, "c");
+    // "https://evil.com", encoded
+    let token = link.path_segments().unwrap().next_back().unwrap().to_owned();
+    let mut parts: Vec<&str> = token.split('.').collect();
+    parts[2] = "aHR0cHM6Ly9ldmlsLmNvbQ";
+    link.set_path(&format!("/t/c/{}", parts.join(".")));
+
+    // Act
+    let response = app.api_client.get(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let n_events = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM newsletter_issue_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_events, 0);
+}
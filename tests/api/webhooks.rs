@@ -0,0 +1,158 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+fn bounce(email: &str, bounce_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": bounce_type,
+        "Email": email,
+        "Description": "The server was unable to deliver your message",
+        "Inactive": bounce_type == "HardBounce",
+    })
+}
+
+#[tokio::test]
+async fn bounce_webhooks_without_the_shared_secret_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    for secret in [None, Some("not-the-secret")] {
+        // Act
+        let mut request = app
+            .api_client
+            .post(format!("{}/webhooks/email/bounce", &app.address))
+            .json(&bounce(&email, "HardBounce"));
+        if let Some(secret) = secret {
+            request = request.header("X-Webhook-Secret", secret);
+        }
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(subscriber_status(&app).await, "confirmed");
+    }
+}
+
+#[tokio::test]
+async fn hard_bounces_suppress_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    // Act
+    let response = app.post_bounce_webhook(&bounce(&email, "HardBounce")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+    let suppression = sqlx::query!("SELECT email, reason FROM email_suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppression.email, email);
+    assert_eq!(suppression.reason, "hard_bounce");
+}
+
+#[tokio::test]
+async fn spam_complaints_suppress_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    // Act
+    let response = app
+        .post_bounce_webhook(&serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Type": "SpamComplaint",
+            "Email": email,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn soft_bounces_are_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    // Act
+    let response = app.post_bounce_webhook(&bounce(&email, "SoftBounce")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    let n_suppressions = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM email_suppressions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_suppressions, 0);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_sent_new_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    app.post_bounce_webhook(&bounce(&email, "HardBounce")).await;
+    // Even if the subscription is confirmed again
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_tasks = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_tasks, 0);
+    // Mock verifies on Drop that no issue has been sent
+}